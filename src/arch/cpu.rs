@@ -0,0 +1,70 @@
+
+//
+//  CPU Control
+//
+
+use arch::idt::DescriptorTablePointer;
+use arch::interrupts::disable_interrupts;
+use arch::io::{inb, outb, io_wait};
+
+/// The 8042 keyboard controller's status (when read) and command (when
+/// written) port.
+const KEYBOARD_CONTROLLER_PORT: u16 = 0x64;
+
+/// The bit in the keyboard controller's status that's set while its input
+/// buffer is full, meaning it isn't ready to accept a command.
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+
+/// The keyboard controller command that pulses the CPU's reset line.
+const KEYBOARD_CONTROLLER_RESET: u8 = 0xfe;
+
+/// The number of times we poll the keyboard controller before giving up on it.
+/// A machine without one reads 0xff from its port, which would otherwise look
+/// like a permanently full input buffer.
+const KEYBOARD_CONTROLLER_TIMEOUT: usize = 0x10000;
+
+/// Halts the CPU forever.
+///
+/// The `hlt` instruction puts the CPU to sleep until the next interrupt
+/// arrives, rather than spinning at full speed in an empty loop. When an
+/// interrupt wakes us up, we just halt again.
+pub fn halt_loop() -> ! {
+	loop {
+		unsafe { asm!("hlt" :::: "volatile") };
+	}
+}
+
+/// Reboots the computer.
+///
+/// We first ask the 8042 keyboard controller to pulse the CPU's reset line,
+/// which works on almost every PC (and in QEMU). If we're still running after
+/// that, we force a triple fault instead: loading an empty IDT means the next
+/// interrupt can't be handled, and neither can the general protection fault or
+/// double fault that follow it, so the CPU gives up and resets.
+pub fn reboot() -> ! {
+	disable_interrupts();
+
+	unsafe {
+		// Wait for the keyboard controller to be ready for a command, then
+		// pulse the reset line
+		for _ in 0..KEYBOARD_CONTROLLER_TIMEOUT {
+			if inb(KEYBOARD_CONTROLLER_PORT) & KEYBOARD_CONTROLLER_INPUT_FULL == 0 {
+				break;
+			}
+		}
+		outb(KEYBOARD_CONTROLLER_PORT, KEYBOARD_CONTROLLER_RESET);
+
+		// Give the reset a moment to take effect
+		for _ in 0..100 {
+			io_wait();
+		}
+
+		// Fall back to a triple fault
+		let pointer = DescriptorTablePointer { limit: 0, base: 0 };
+		asm!("lidt ($0)" :: "r"(&pointer) : "memory" : "volatile");
+		asm!("int3" :::: "volatile");
+	}
+
+	// We should never get here, but make sure this function doesn't return
+	halt_loop();
+}
@@ -0,0 +1,79 @@
+
+//
+//  CPU Feature Detection
+//
+
+/// The standard leaf holding the CPU's basic feature flags.
+const LEAF_FEATURES: u32 = 0x1;
+
+/// The extended leaf that reports the highest supported extended leaf.
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+
+/// The extended leaf holding AMD's extended feature flags (including the
+/// no-execute bit, which Intel also reports here).
+const LEAF_EXTENDED_FEATURES: u32 = 0x8000_0001;
+
+/// Bit 9 in edx for `LEAF_FEATURES`, set if the CPU has a local APIC.
+const FEATURES_EDX_APIC: u32 = 1 << 9;
+
+/// Bit 20 in edx for `LEAF_EXTENDED_FEATURES`, set if the CPU supports the
+/// no-execute bit in page table entries.
+const EXTENDED_EDX_NX: u32 = 1 << 20;
+
+/// Bit 26 in edx for `LEAF_EXTENDED_FEATURES`, set if the CPU supports 1 GiB
+/// pages.
+const EXTENDED_EDX_1GIB_PAGES: u32 = 1 << 26;
+
+/// The registers returned by the `cpuid` instruction for a leaf.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuidResult {
+	pub eax: u32,
+	pub ebx: u32,
+	pub ecx: u32,
+	pub edx: u32,
+}
+
+/// Executes the `cpuid` instruction for the given leaf (with a sub-leaf of 0).
+///
+/// Leaves above the highest one the CPU supports return meaningless values, so
+/// check the maximum (returned in eax for leaf 0, or `0x8000_0000` for the
+/// extended leaves) first.
+pub fn cpuid(leaf: u32) -> CpuidResult {
+	let eax: u32;
+	let ebx: u32;
+	let ecx: u32;
+	let edx: u32;
+	unsafe {
+		asm!("cpuid"
+			: "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx), "={edx}"(edx)
+			: "{eax}"(leaf), "{ecx}"(0)
+			:: "volatile");
+	}
+	CpuidResult { eax: eax, ebx: ebx, ecx: ecx, edx: edx }
+}
+
+/// Returns the edx register for the extended features leaf, or 0 if the CPU
+/// doesn't support the leaf (so every feature reads as unsupported).
+fn extended_features_edx() -> u32 {
+	if cpuid(LEAF_EXTENDED_MAX).eax < LEAF_EXTENDED_FEATURES {
+		return 0;
+	}
+	cpuid(LEAF_EXTENDED_FEATURES).edx
+}
+
+/// Returns true if the CPU supports the no-execute bit in page table entries.
+pub fn has_nx() -> bool {
+	extended_features_edx() & EXTENDED_EDX_NX != 0
+}
+
+/// Returns true if the CPU supports 1 GiB pages (mapped by P3 entries).
+pub fn has_1gib_pages() -> bool {
+	extended_features_edx() & EXTENDED_EDX_1GIB_PAGES != 0
+}
+
+/// Returns true if the CPU has a local APIC.
+pub fn has_apic() -> bool {
+	// Every 64 bit CPU supports leaf 1, so there's no need to check the
+	// maximum leaf first
+	cpuid(LEAF_FEATURES).edx & FEATURES_EDX_APIC != 0
+}
@@ -0,0 +1,243 @@
+
+//
+//  Global Descriptor Table
+//
+
+use spin::{Mutex, Once};
+
+use core::mem::size_of;
+
+use arch::idt::DescriptorTablePointer;
+use arch::tss::TaskStateSegment;
+
+/// The maximum number of 8 byte entries in our GDT. Note that a TSS descriptor
+/// takes up 2 entries.
+const GDT_ENTRIES: usize = 8;
+
+/// The selector for the kernel's code segment.
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector(1 * 8);
+
+/// The selector for the kernel's data segment.
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector(2 * 8);
+
+/// The selector for the TSS.
+pub const TSS_SELECTOR: SegmentSelector = SegmentSelector(3 * 8);
+
+/// The index into the TSS's interrupt stack table of the stack used by the
+/// double fault handler.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// The size of the stack used by the double fault handler.
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 4;
+
+/// The stack used by the double fault handler. A double fault can be caused by
+/// the kernel's own stack overflowing, so the handler can't use that stack.
+static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] =
+	[0; DOUBLE_FAULT_STACK_SIZE];
+
+/// The kernel's TSS. The CPU reads the interrupt stack table from this after
+/// it's loaded, so it must be a static.
+static TSS: Mutex<TaskStateSegment> = Mutex::new(TaskStateSegment::new());
+
+/// The kernel's GDT. Like the TSS, this must be a static.
+static GDT: Mutex<Gdt> = Mutex::new(Gdt::new());
+
+/// Flags for code and data segment descriptors. In long mode, segmentation is
+/// mostly disabled, so most of each descriptor's fields are ignored.
+///
+/// Set for code and data segments (as opposed to system segments, like the
+/// TSS).
+const DESCRIPTOR_USER_SEGMENT: u64 = 1 << 44;
+
+/// Set for all valid descriptors.
+const DESCRIPTOR_PRESENT: u64 = 1 << 47;
+
+/// Set for code segments.
+const DESCRIPTOR_EXECUTABLE: u64 = 1 << 43;
+
+/// Set to make data segments writable (and code segments readable).
+const DESCRIPTOR_READ_WRITE: u64 = 1 << 41;
+
+/// Set for 64 bit code segments.
+const DESCRIPTOR_LONG_MODE: u64 = 1 << 53;
+
+/// The system segment type for an available 64 bit TSS.
+const DESCRIPTOR_TSS_AVAILABLE: u64 = 0b1001 << 40;
+
+/// A selector for a segment in the GDT, loaded into a segment register or
+/// passed to `ltr`. Stores the descriptor's byte offset into the GDT, and the
+/// requested privilege level in the lowest 2 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentSelector(pub u16);
+
+/// A descriptor to add to the GDT.
+enum Descriptor {
+	/// A code or data segment, which takes up 1 entry.
+	UserSegment(u64),
+
+	/// A system segment (like a TSS), which takes up 2 entries.
+	SystemSegment(u64, u64),
+}
+
+impl Descriptor {
+	/// Create a descriptor for a 64 bit kernel code segment.
+	fn kernel_code_segment() -> Descriptor {
+		Descriptor::UserSegment(DESCRIPTOR_USER_SEGMENT | DESCRIPTOR_PRESENT |
+			DESCRIPTOR_EXECUTABLE | DESCRIPTOR_LONG_MODE)
+	}
+
+	/// Create a descriptor for a kernel data segment.
+	fn kernel_data_segment() -> Descriptor {
+		Descriptor::UserSegment(DESCRIPTOR_USER_SEGMENT | DESCRIPTOR_PRESENT |
+			DESCRIPTOR_READ_WRITE)
+	}
+
+	/// Create a descriptor for a TSS.
+	fn tss_segment(tss: &'static TaskStateSegment) -> Descriptor {
+		let base = tss as *const _ as u64;
+		let limit = (size_of::<TaskStateSegment>() - 1) as u64;
+
+		// The TSS's base address is split up across both entries, in the same
+		// awkward layout as the 32 bit descriptors
+		let low = DESCRIPTOR_PRESENT | DESCRIPTOR_TSS_AVAILABLE |
+			(limit & 0xffff) |
+			((base & 0xffffff) << 16) |
+			((base >> 24 & 0xff) << 56);
+		let high = base >> 32;
+		Descriptor::SystemSegment(low, high)
+	}
+}
+
+/// Stores the segment descriptors the CPU uses.
+struct Gdt {
+	entries: [u64; GDT_ENTRIES],
+
+	/// The index of the next free entry. The first entry must always be 0.
+	next_free: usize,
+}
+
+impl Gdt {
+	/// Create a new GDT, containing only the required empty entry.
+	const fn new() -> Gdt {
+		Gdt {
+			entries: [0; GDT_ENTRIES],
+			next_free: 1,
+		}
+	}
+
+	/// Adds a descriptor to the GDT, returning the selector for it.
+	fn add_entry(&mut self, descriptor: Descriptor) -> SegmentSelector {
+		let index = match descriptor {
+			Descriptor::UserSegment(value) => self.push(value),
+			Descriptor::SystemSegment(low, high) => {
+				let index = self.push(low);
+				self.push(high);
+				index
+			},
+		};
+
+		// All our segments are used by the kernel, so request privilege
+		// level 0
+		SegmentSelector((index * 8) as u16)
+	}
+
+	/// Adds a raw value to the next free entry, returning its index.
+	fn push(&mut self, value: u64) -> usize {
+		if self.next_free >= GDT_ENTRIES {
+			panic!("GDT full");
+		}
+
+		let index = self.next_free;
+		self.entries[index] = value;
+		self.next_free += 1;
+		index
+	}
+
+	/// Loads the GDT into the CPU using the `lgdt` instruction.
+	///
+	/// This is unsafe because the CPU keeps using the table at this address
+	/// until another is loaded, so it must never be moved or dropped.
+	unsafe fn load(&self) {
+		let pointer = DescriptorTablePointer {
+			limit: (self.entries.len() * 8 - 1) as u16,
+			base: self.entries.as_ptr() as u64,
+		};
+		asm!("lgdt ($0)" :: "r"(&pointer) : "memory" : "volatile");
+	}
+}
+
+/// Reloads the code segment register with a new selector.
+///
+/// The code segment can't be set using `mov`, so we push the selector and the
+/// address to return to onto the stack, and do a far return.
+unsafe fn set_code_segment(selector: SegmentSelector) {
+	asm!("pushq $0
+		leaq 1f(%rip), %rax
+		pushq %rax
+		lretq
+		1:"
+		:: "r"(selector.0 as u64)
+		: "rax" "memory"
+		: "volatile");
+}
+
+/// Reloads the data and stack segment registers with a new selector.
+unsafe fn set_data_segments(selector: SegmentSelector) {
+	asm!("mov $0, %ds
+		mov $0, %es
+		mov $0, %ss"
+		:: "r"(selector.0)
+		: "memory"
+		: "volatile");
+}
+
+/// Loads the task register with the selector of the TSS to use.
+unsafe fn load_task_register(selector: SegmentSelector) {
+	asm!("ltr $0" :: "r"(selector.0) : "memory" : "volatile");
+}
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the GDT module.
+///
+/// Replaces the GDT set up in `start.asm` with one containing a kernel code
+/// segment, a kernel data segment, and a TSS whose interrupt stack table holds
+/// a separate stack for the double fault handler.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		let mut tss = TSS.lock();
+
+		// The stack grows downwards, so the stack pointer starts at the end of
+		// the stack
+		let stack_top = unsafe {
+			DOUBLE_FAULT_STACK.as_ptr() as u64 + DOUBLE_FAULT_STACK_SIZE as u64
+		};
+		tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_top;
+
+		// It's safe to extend the TSS's lifetime to 'static because it's stored
+		// in a static, so never moves
+		let tss: &'static TaskStateSegment =
+			unsafe { &*(&*tss as *const TaskStateSegment) };
+
+		let mut gdt = GDT.lock();
+		let code = gdt.add_entry(Descriptor::kernel_code_segment());
+		let data = gdt.add_entry(Descriptor::kernel_data_segment());
+		let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+
+		// Make sure the selectors we give out to the rest of the kernel match
+		// the entries we actually created
+		assert_eq!(code, KERNEL_CODE_SELECTOR);
+		assert_eq!(data, KERNEL_DATA_SELECTOR);
+		assert_eq!(tss_selector, TSS_SELECTOR);
+
+		unsafe {
+			gdt.load();
+			set_code_segment(code);
+			set_data_segments(data);
+			load_task_register(tss_selector);
+		}
+	});
+}
@@ -0,0 +1,169 @@
+
+//
+//  Interrupt Descriptor Table
+//
+
+use arch::gdt::KERNEL_CODE_SELECTOR;
+
+use core::fmt;
+
+/// The number of entries in the IDT, one for each interrupt vector the CPU
+/// supports.
+const IDT_ENTRIES: usize = 256;
+
+/// The bits in an IDT entry's options field that mark it as a 64 bit
+/// interrupt gate (as opposed to a trap gate), which disables interrupts while
+/// the handler runs.
+const INTERRUPT_GATE: u16 = 0xe << 8;
+
+/// The bit in an IDT entry's options field that marks it as present. The CPU
+/// raises a general protection fault if an interrupt arrives for a vector
+/// whose entry isn't present.
+const PRESENT: u16 = 1 << 15;
+
+/// The information the CPU pushes onto the stack before calling an interrupt
+/// handler.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ExceptionStackFrame {
+	pub instruction_pointer: u64,
+	pub code_segment: u64,
+	pub cpu_flags: u64,
+	pub stack_pointer: u64,
+	pub stack_segment: u64,
+}
+
+impl fmt::Display for ExceptionStackFrame {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "rip: {:#018x}  cs: {:#06x}  rflags: {:#010x}",
+			self.instruction_pointer, self.code_segment, self.cpu_flags)?;
+		write!(f, "rsp: {:#018x}  ss: {:#06x}", self.stack_pointer,
+			self.stack_segment)
+	}
+}
+
+/// An interrupt handler, for interrupts that don't push an error code.
+///
+/// The `x86-interrupt` calling convention saves and restores every register the
+/// handler uses, and returns using `iretq` rather than `ret`.
+pub type HandlerFunc = extern "x86-interrupt" fn(&mut ExceptionStackFrame);
+
+/// An interrupt handler, for exceptions that push an error code onto the stack
+/// along with the stack frame.
+pub type HandlerFuncWithErrorCode =
+	extern "x86-interrupt" fn(&mut ExceptionStackFrame, u64);
+
+/// The structure passed to the `lidt` and `lgdt` instructions, giving the size
+/// and location of a descriptor table.
+#[repr(C, packed)]
+pub struct DescriptorTablePointer {
+	/// The size of the table in bytes, minus 1.
+	pub limit: u16,
+
+	/// The virtual address of the start of the table.
+	pub base: u64,
+}
+
+/// A single 16 byte gate descriptor in the IDT, telling the CPU where to jump
+/// to when an interrupt arrives.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct Entry {
+	/// The handler's address is split across 3 fields.
+	pointer_low: u16,
+
+	/// The code segment selector the CPU switches to when calling the
+	/// handler.
+	selector: u16,
+
+	/// Bits 0-2 are an index into the interrupt stack table (0 means don't
+	/// switch stacks), bits 8-11 the gate type, bits 13-14 the privilege level
+	/// required to trigger the interrupt in software, and bit 15 the present
+	/// flag.
+	options: u16,
+
+	pointer_middle: u16,
+	pointer_high: u32,
+	reserved: u32,
+}
+
+impl Entry {
+	/// Create a non-present entry.
+	const fn missing() -> Entry {
+		Entry {
+			pointer_low: 0,
+			selector: 0,
+			options: INTERRUPT_GATE,
+			pointer_middle: 0,
+			pointer_high: 0,
+			reserved: 0,
+		}
+	}
+
+	/// Points the entry at the handler with the given address, and marks it
+	/// present.
+	fn set_handler_address(&mut self, address: u64) {
+		self.pointer_low = address as u16;
+		self.pointer_middle = (address >> 16) as u16;
+		self.pointer_high = (address >> 32) as u32;
+
+		// Run the handler in the kernel's code segment
+		self.selector = KERNEL_CODE_SELECTOR.0;
+		self.options = INTERRUPT_GATE | PRESENT;
+	}
+
+	/// Sets the index into the TSS's interrupt stack table of the stack the
+	/// CPU switches to before calling this entry's handler.
+	///
+	/// This is unsafe because the index must refer to a valid stack in the
+	/// loaded TSS that isn't used by any other handler.
+	pub unsafe fn set_stack_index(&mut self, index: u16) {
+		// The index is stored in the lowest 3 bits, where 0 means don't switch
+		// stacks
+		self.options = (self.options & !0b111) | (index + 1);
+	}
+}
+
+/// Tells the CPU which function to call for each interrupt vector.
+pub struct InterruptDescriptorTable {
+	entries: [Entry; IDT_ENTRIES],
+}
+
+impl InterruptDescriptorTable {
+	/// Create a new IDT, where no vectors have handlers.
+	pub const fn new() -> InterruptDescriptorTable {
+		InterruptDescriptorTable {
+			entries: [Entry::missing(); IDT_ENTRIES],
+		}
+	}
+
+	/// Sets the handler for an interrupt vector that doesn't push an error
+	/// code.
+	pub fn set_handler(&mut self, vector: u8, handler: HandlerFunc)
+			-> &mut Entry {
+		let entry = &mut self.entries[vector as usize];
+		entry.set_handler_address(handler as u64);
+		entry
+	}
+
+	/// Sets the handler for an exception vector that pushes an error code.
+	pub fn set_handler_with_error_code(&mut self, vector: u8,
+			handler: HandlerFuncWithErrorCode) -> &mut Entry {
+		let entry = &mut self.entries[vector as usize];
+		entry.set_handler_address(handler as u64);
+		entry
+	}
+
+	/// Loads the IDT into the CPU using the `lidt` instruction.
+	///
+	/// This is unsafe because the CPU keeps using the table at this address
+	/// until another is loaded, so it must never be moved or dropped (ie. it
+	/// should be a static).
+	pub unsafe fn load(&self) {
+		let pointer = DescriptorTablePointer {
+			limit: (IDT_ENTRIES * 16 - 1) as u16,
+			base: self as *const _ as u64,
+		};
+		asm!("lidt ($0)" :: "r"(&pointer) : "memory" : "volatile");
+	}
+}
@@ -0,0 +1,293 @@
+
+//
+//  Interrupts and Exceptions
+//
+
+use spin::{Mutex, Once};
+
+use arch::cpu::halt_loop;
+use arch::gdt::DOUBLE_FAULT_IST_INDEX;
+use arch::idt::{InterruptDescriptorTable, ExceptionStackFrame, HandlerFunc};
+use arch::paging::read_cr2;
+use driver::vga::Color;
+use debug::{backtrace, hex_dump};
+
+/// The interrupt flag (IF) in the rflags register, set while hardware
+/// interrupts are enabled.
+const RFLAGS_INTERRUPT: u64 = 1 << 9;
+
+/// The interrupt vectors of the CPU exceptions we handle.
+const DIVIDE_BY_ZERO: u8 = 0;
+const BREAKPOINT: u8 = 3;
+const INVALID_OPCODE: u8 = 6;
+const DOUBLE_FAULT: u8 = 8;
+const GENERAL_PROTECTION_FAULT: u8 = 13;
+const PAGE_FAULT: u8 = 14;
+
+/// The meaning of each bit in the error code pushed by a page fault.
+///
+/// Set if the fault was caused by a protection violation, rather than the page
+/// not being present.
+const PAGE_FAULT_PROTECTION: u64 = 1 << 0;
+
+/// Set if the fault was caused by a write, rather than a read.
+const PAGE_FAULT_WRITE: u64 = 1 << 1;
+
+/// Set if the fault happened while running in user mode.
+const PAGE_FAULT_USER: u64 = 1 << 2;
+
+/// Set if a reserved bit was set in one of the page table entries.
+const PAGE_FAULT_RESERVED: u64 = 1 << 3;
+
+/// Set if the fault was caused by fetching an instruction.
+const PAGE_FAULT_INSTRUCTION_FETCH: u64 = 1 << 4;
+
+/// The meaning of each bit in a selector error code, pushed by a general
+/// protection fault caused by a segment selector.
+///
+/// Set if the fault was caused by an event external to the program (eg. a
+/// hardware interrupt).
+const SELECTOR_ERROR_EXTERNAL: u64 = 1 << 0;
+
+/// The shift and mask of the bits saying which descriptor table the selector
+/// refers to.
+const SELECTOR_ERROR_TABLE_SHIFT: u64 = 1;
+const SELECTOR_ERROR_TABLE_MASK: u64 = 0b11;
+
+/// The shift and mask of the bits holding the selector's index into its table.
+const SELECTOR_ERROR_INDEX_SHIFT: u64 = 3;
+const SELECTOR_ERROR_INDEX_MASK: u64 = 0x1fff;
+
+/// The longest an x86 instruction can be, in bytes.
+const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+/// The kernel's IDT. This must be a static, since the CPU keeps referring to it
+/// after it's loaded.
+static IDT: Mutex<InterruptDescriptorTable> =
+	Mutex::new(InterruptDescriptorTable::new());
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the interrupts module.
+///
+/// Sets up handlers for the CPU exceptions and loads the IDT. Must be called
+/// after `gdt::init`, which sets up the double fault handler's stack.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		let mut idt = IDT.lock();
+		idt.set_handler(DIVIDE_BY_ZERO, divide_by_zero_handler);
+		idt.set_handler(BREAKPOINT, breakpoint_handler);
+		idt.set_handler(INVALID_OPCODE, invalid_opcode_handler);
+		idt.set_handler_with_error_code(GENERAL_PROTECTION_FAULT,
+			general_protection_fault_handler);
+		idt.set_handler_with_error_code(PAGE_FAULT, page_fault_handler);
+
+		// Run the double fault handler on its own stack, since a double fault
+		// can be caused by the kernel's stack overflowing. The stack is set up
+		// in the TSS by `gdt::init`
+		unsafe {
+			idt.set_handler_with_error_code(DOUBLE_FAULT, double_fault_handler)
+				.set_stack_index(DOUBLE_FAULT_IST_INDEX);
+		}
+
+		// It's safe to load the IDT because it's stored in a static
+		unsafe { idt.load() };
+	});
+}
+
+/// Sets the handler for an interrupt vector, eg. for a device's IRQ.
+pub fn set_interrupt_handler(vector: u8, handler: HandlerFunc) {
+	// The IDT's already loaded, but the CPU reads an entry from memory each
+	// time its interrupt arrives, so changes take effect straight away
+	IDT.lock().set_handler(vector, handler);
+}
+
+/// Enables hardware interrupts.
+pub fn enable_interrupts() {
+	unsafe { asm!("sti" :::: "volatile") };
+}
+
+/// Disables hardware interrupts.
+pub fn disable_interrupts() {
+	unsafe { asm!("cli" :::: "volatile") };
+}
+
+/// Returns true if hardware interrupts are enabled.
+pub fn interrupts_enabled() -> bool {
+	// We can't read rflags directly, so push it onto the stack and pop it into
+	// a general purpose register
+	let flags: u64;
+	unsafe { asm!("pushfq; popq $0" : "=r"(flags) :: "memory" : "volatile") };
+	flags & RFLAGS_INTERRUPT != 0
+}
+
+/// Disables interrupts while it exists, and restores them to their previous
+/// state when dropped.
+///
+/// Code that takes a lock an interrupt handler might also take (like the VGA
+/// writer's) must hold one of these, or the handler could spin forever waiting
+/// for a lock that the code it interrupted holds.
+pub struct InterruptGuard {
+	/// Whether interrupts were enabled when the guard was created.
+	enabled: bool,
+}
+
+impl InterruptGuard {
+	/// Disables interrupts, remembering whether they were enabled.
+	pub fn new() -> InterruptGuard {
+		// Read the interrupt flag before disabling interrupts, so that nested
+		// guards don't enable interrupts when the inner one is dropped
+		let enabled = interrupts_enabled();
+		disable_interrupts();
+		InterruptGuard {
+			enabled: enabled,
+		}
+	}
+}
+
+impl Drop for InterruptGuard {
+	fn drop(&mut self) {
+		if self.enabled {
+			enable_interrupts();
+		}
+	}
+}
+
+/// Runs a function with interrupts disabled, restoring them to their previous
+/// state afterwards.
+pub fn without_interrupts<F, R>(f: F) -> R where F: FnOnce() -> R {
+	let _guard = InterruptGuard::new();
+	f()
+}
+
+/// Prints the stack frame pushed by the CPU for an exception, to both the
+/// terminal and the serial port.
+fn print_stack_frame(frame: &ExceptionStackFrame) {
+	println!("{}", frame);
+	serial_println!("{}", frame);
+}
+
+/// Prints the name of an exception that we can't recover from, its error code
+/// (if it has one) and the stack frame pushed by the CPU, then halts.
+///
+/// The general purpose registers aren't printed: the `x86-interrupt` handlers
+/// have already used them by the time we get here, so they wouldn't show the
+/// state of the code that faulted.
+fn fatal_exception(name: &str, frame: &ExceptionStackFrame,
+		error_code: Option<u64>) -> ! {
+	let rip = frame.instruction_pointer;
+	match error_code {
+		Some(code) => {
+			color_println!(Color::Red, Color::Black,
+				"EXCEPTION: {} at {:#x} (error code {:#x})", name, rip, code);
+			serial_println!("EXCEPTION: {} at {:#x} (error code {:#x})", name,
+				rip, code);
+		},
+		None => {
+			color_println!(Color::Red, Color::Black, "EXCEPTION: {} at {:#x}",
+				name, rip);
+			serial_println!("EXCEPTION: {} at {:#x}", name, rip);
+		},
+	}
+	print_stack_frame(frame);
+	backtrace();
+	halt_loop();
+}
+
+extern "x86-interrupt" fn breakpoint_handler(frame: &mut ExceptionStackFrame) {
+	// Unlike other exceptions, we continue running after a breakpoint. The
+	// `x86-interrupt` calling convention restores every register we use and
+	// returns with `iretq`. The pushed instruction pointer is the instruction
+	// after the `int3`, so that's where execution resumes
+	println!("BREAKPOINT at {:#x}", frame.instruction_pointer);
+	serial_println!("BREAKPOINT at {:#x}", frame.instruction_pointer);
+	print_stack_frame(frame);
+}
+
+extern "x86-interrupt" fn divide_by_zero_handler(frame: &mut ExceptionStackFrame) {
+	fatal_exception("divide by zero", frame, None);
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame: &mut ExceptionStackFrame) {
+	// Show the bytes the CPU failed to decode. `fatal_exception` prints the
+	// exception and its address. The instruction was just fetched, so it's at
+	// least partly mapped, though it might cross into an unmapped page (in
+	// which case we'll page fault here)
+	let rip = frame.instruction_pointer as usize;
+	unsafe { hex_dump(rip, MAX_INSTRUCTION_LENGTH) };
+
+	fatal_exception("invalid opcode", frame, None);
+}
+
+extern "x86-interrupt" fn double_fault_handler(frame: &mut ExceptionStackFrame,
+		error_code: u64) {
+	fatal_exception("double fault", frame, Some(error_code));
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler(
+		frame: &mut ExceptionStackFrame, error_code: u64) {
+	// The error code is only non-zero if the fault was caused by loading a
+	// segment selector, in which case it identifies the selector
+	if error_code == 0 {
+		color_println!(Color::Red, Color::Black,
+			"GENERAL PROTECTION FAULT: not caused by a segment selector");
+		serial_println!("GENERAL PROTECTION FAULT: not caused by a segment selector");
+	} else {
+		let table = match (error_code >> SELECTOR_ERROR_TABLE_SHIFT) &
+				SELECTOR_ERROR_TABLE_MASK {
+			0 => "GDT",
+			2 => "LDT",
+			_ => "IDT",
+		};
+		let index = (error_code >> SELECTOR_ERROR_INDEX_SHIFT) &
+			SELECTOR_ERROR_INDEX_MASK;
+		let source = if error_code & SELECTOR_ERROR_EXTERNAL != 0 {
+			"an external event"
+		} else {
+			"the program"
+		};
+
+		color_println!(Color::Red, Color::Black,
+			"GENERAL PROTECTION FAULT: selector {:#x} (entry {} in the {}), caused by {}",
+			error_code, index, table, source);
+		serial_println!(
+			"GENERAL PROTECTION FAULT: selector {:#x} (entry {} in the {}), caused by {}",
+			error_code, index, table, source);
+	}
+
+	fatal_exception("general protection fault", frame, Some(error_code));
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: &mut ExceptionStackFrame,
+		error_code: u64) {
+	// The faulting address is in cr2. Exceptions can't be masked, so a page
+	// fault inside this handler would overwrite it. Read it before doing
+	// anything else that could fault
+	let address = read_cr2();
+
+	// Decode the error code
+	let access = if error_code & PAGE_FAULT_INSTRUCTION_FETCH != 0 {
+		"instruction fetch"
+	} else if error_code & PAGE_FAULT_WRITE != 0 {
+		"write"
+	} else {
+		"read"
+	};
+	let mode = if error_code & PAGE_FAULT_USER != 0 { "user" } else { "kernel" };
+	let cause = if error_code & PAGE_FAULT_RESERVED != 0 {
+		"reserved bit set in page table entry"
+	} else if error_code & PAGE_FAULT_PROTECTION != 0 {
+		"protection violation"
+	} else {
+		"page not present"
+	};
+
+	color_println!(Color::Red, Color::Black, "PAGE FAULT: {} of {:#x} in {} mode: {}",
+		access, address, mode, cause);
+	serial_println!("PAGE FAULT: {} of {:#x} in {} mode: {}", access, address,
+		mode, cause);
+	fatal_exception("page fault", frame, Some(error_code));
+}
@@ -0,0 +1,125 @@
+
+//
+//  Port IO
+//
+
+use core::marker::PhantomData;
+
+/// Reads a byte from an IO port.
+pub unsafe fn inb(port: u16) -> u8 {
+	let value: u8;
+	asm!("inb %dx, %al" : "={al}"(value) : "{dx}"(port) :: "volatile");
+	value
+}
+
+/// Writes a byte to an IO port.
+pub unsafe fn outb(port: u16, value: u8) {
+	asm!("outb %al, %dx" :: "{dx}"(port), "{al}"(value) :: "volatile");
+}
+
+/// Reads a 16 bit word from an IO port.
+pub unsafe fn inw(port: u16) -> u16 {
+	let value: u16;
+	asm!("inw %dx, %ax" : "={ax}"(value) : "{dx}"(port) :: "volatile");
+	value
+}
+
+/// Writes a 16 bit word to an IO port.
+pub unsafe fn outw(port: u16, value: u16) {
+	asm!("outw %ax, %dx" :: "{dx}"(port), "{ax}"(value) :: "volatile");
+}
+
+/// Reads a 32 bit double word from an IO port.
+pub unsafe fn inl(port: u16) -> u32 {
+	let value: u32;
+	asm!("inl %dx, %eax" : "={eax}"(value) : "{dx}"(port) :: "volatile");
+	value
+}
+
+/// Writes a 32 bit double word to an IO port.
+pub unsafe fn outl(port: u16, value: u32) {
+	asm!("outl %eax, %dx" :: "{dx}"(port), "{eax}"(value) :: "volatile");
+}
+
+/// A value that can be read from and written to an IO port. Implemented for
+/// each of the sizes that the `in` and `out` instructions support.
+pub trait PortValue {
+	/// Reads a value of this size from the IO port.
+	unsafe fn read(port: u16) -> Self;
+
+	/// Writes a value of this size to the IO port.
+	unsafe fn write(port: u16, value: Self);
+}
+
+impl PortValue for u8 {
+	unsafe fn read(port: u16) -> u8 {
+		inb(port)
+	}
+
+	unsafe fn write(port: u16, value: u8) {
+		outb(port, value);
+	}
+}
+
+impl PortValue for u16 {
+	unsafe fn read(port: u16) -> u16 {
+		inw(port)
+	}
+
+	unsafe fn write(port: u16, value: u16) {
+		outw(port, value);
+	}
+}
+
+impl PortValue for u32 {
+	unsafe fn read(port: u16) -> u32 {
+		inl(port)
+	}
+
+	unsafe fn write(port: u16, value: u32) {
+		outl(port, value);
+	}
+}
+
+/// An IO port which values of type `T` are read from and written to, so that
+/// drivers can declare their ports as typed fields.
+pub struct Port<T: PortValue> {
+	/// The port number.
+	port: u16,
+
+	/// Marks that the port uses values of type `T`, without storing one.
+	phantom: PhantomData<T>,
+}
+
+impl<T: PortValue> Port<T> {
+	/// Create a new port with the given port number.
+	pub const fn new(port: u16) -> Port<T> {
+		Port {
+			port: port,
+			phantom: PhantomData,
+		}
+	}
+
+	/// Reads a value from the port.
+	///
+	/// This is unsafe because reading from a port can have side effects on the
+	/// device behind it.
+	pub unsafe fn read(&mut self) -> T {
+		T::read(self.port)
+	}
+
+	/// Writes a value to the port.
+	///
+	/// This is unsafe because writing to a port can make the device behind it
+	/// do anything, including overwrite arbitrary memory.
+	pub unsafe fn write(&mut self, value: T) {
+		T::write(self.port, value);
+	}
+}
+
+/// Waits a very short amount of time (around 1 to 4 microseconds), by writing
+/// to an unused IO port. Some older devices need a short delay between
+/// successive writes while they're being configured.
+pub fn io_wait() {
+	unsafe { outb(0x80, 0) };
+}
@@ -0,0 +1,16 @@
+
+//
+//  Architecture Specific Code
+//
+
+pub mod cpu;
+pub mod cpuid;
+pub mod gdt;
+pub mod idt;
+pub mod interrupts;
+pub mod io;
+pub mod msr;
+pub mod paging;
+pub mod qemu;
+pub mod tsc;
+pub mod tss;
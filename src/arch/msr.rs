@@ -0,0 +1,38 @@
+
+//
+//  Model Specific Registers
+//
+
+/// The Extended Feature Enable Register (EFER), which controls long mode and
+/// no-execute support.
+pub const EFER: u32 = 0xC0000080;
+
+/// Reads a 64 bit model specific register (MSR).
+///
+/// This is unsafe because reading an MSR that doesn't exist on this CPU raises
+/// a general protection fault.
+pub unsafe fn read_msr(msr: u32) -> u64 {
+	// The `rdmsr` instruction reads the MSR given in `ecx` and splits its
+	// 64 bit value across `edx` (high 32 bits) and `eax` (low 32 bits)
+	let high: u32;
+	let low: u32;
+	asm!("rdmsr"
+		: "={eax}"(low), "={edx}"(high)
+		: "{ecx}"(msr)
+		: "memory"
+		: "volatile");
+	(high as u64) << 32 | low as u64
+}
+
+/// Writes a 64 bit model specific register (MSR).
+///
+/// This is unsafe because MSRs control fundamental CPU behaviour, and writing
+/// to one that doesn't exist raises a general protection fault.
+pub unsafe fn write_msr(msr: u32, value: u64) {
+	// The `wrmsr` instruction takes its arguments in the same registers as
+	// `rdmsr`
+	asm!("wrmsr"
+		:: "{ecx}"(msr), "{eax}"(value as u32), "{edx}"((value >> 32) as u32)
+		: "memory"
+		: "volatile");
+}
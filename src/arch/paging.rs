@@ -0,0 +1,148 @@
+
+//
+//  Paging Control
+//
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use arch::cpuid;
+use arch::msr::{EFER, read_msr, write_msr};
+
+/// Bit 11 in the EFER is the No-Execute Enable (NXE) bit. Until it's set, the
+/// CPU treats bit 63 of a page table entry as reserved, and raises a page fault
+/// when it finds an entry with that bit set.
+const EFER_NXE: u64 = 1 << 11;
+
+/// Bit 0 in a page table entry, set if the entry maps a page or table.
+pub const ENTRY_PRESENT: u64 = 1 << 0;
+
+/// Bit 1 in a page table entry, set if the memory it maps can be written to.
+pub const ENTRY_WRITABLE: u64 = 1 << 1;
+
+/// Bit 3 in a page table entry (PWT), which makes writes to the memory it maps
+/// go straight through to memory rather than being held in the cache.
+pub const ENTRY_WRITE_THROUGH: u64 = 1 << 3;
+
+/// Bit 4 in a page table entry (PCD), which stops the memory it maps from being
+/// cached at all.
+pub const ENTRY_NO_CACHE: u64 = 1 << 4;
+
+/// The flags to use when mapping memory mapped device registers (like the APIC
+/// or a framebuffer). Caching must be disabled for these, otherwise the CPU may
+/// combine or reorder reads and writes that the device relies on seeing in
+/// order.
+pub const ENTRY_MMIO: u64 = ENTRY_PRESENT | ENTRY_WRITABLE | ENTRY_NO_CACHE;
+
+/// Bit 63 in a page table entry, which prevents instructions from being
+/// fetched from the memory it maps. Only valid once the NXE bit is enabled.
+pub const ENTRY_NO_EXECUTE: u64 = 1 << 63;
+
+/// Whether the NXE bit has been enabled in the EFER yet.
+static NXE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Enables the NXE bit in the EFER, allowing page table entries to use the
+/// no-execute bit.
+///
+/// Does nothing if the CPU doesn't support the no-execute bit, in which case
+/// `nxe_enabled` stays false and the paging code must leave the bit clear.
+pub fn enable_nxe() {
+	// Setting the NXE bit on a CPU without no-execute support raises a general
+	// protection fault
+	if !cpuid::has_nx() {
+		return;
+	}
+
+	unsafe {
+		let efer = read_msr(EFER);
+		write_msr(EFER, efer | EFER_NXE);
+	}
+
+	NXE_ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if the NXE bit has been enabled. The paging code must check
+/// this before setting `ENTRY_NO_EXECUTE` on an entry, since the CPU faults on
+/// any entry using the bit otherwise.
+pub fn nxe_enabled() -> bool {
+	NXE_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Bit 16 in the cr0 register is the Write Protect (WP) bit. Without it, the
+/// CPU ignores the writable bit in page table entries while in kernel mode, so
+/// the kernel can write to read only pages.
+const CR0_WRITE_PROTECT: usize = 1 << 16;
+
+/// Enables the write protect bit in cr0, so that read only page table entries
+/// are enforced for the kernel as well as user mode.
+pub fn enable_write_protect() {
+	unsafe {
+		let cr0: usize;
+		asm!("mov %cr0, $0" : "=r"(cr0) ::: "volatile");
+		asm!("mov $0, %cr0" :: "r"(cr0 | CR0_WRITE_PROTECT) : "memory" : "volatile");
+	}
+}
+
+/// A virtual memory address.
+pub type VirtualAddr = usize;
+
+/// A physical memory address.
+pub type PhysicalAddr = usize;
+
+/// The bits in cr3 holding the physical address of the P4 table. The lowest 12
+/// bits are flags (since the table is page aligned), and the highest 12 are
+/// reserved.
+const CR3_ADDRESS_MASK: usize = 0x000f_ffff_ffff_f000;
+
+/// Returns the full value of cr3, including the flag and PCID bits alongside
+/// the P4 table's address.
+fn read_cr3_raw() -> usize {
+	let cr3: usize;
+	unsafe { asm!("mov %cr3, $0" : "=r"(cr3) ::: "volatile") };
+	cr3
+}
+
+/// Returns the physical address of the active P4 table, stored in cr3.
+pub fn read_cr3() -> PhysicalAddr {
+	read_cr3_raw() & CR3_ADDRESS_MASK
+}
+
+/// Sets the physical address of the active P4 table in cr3, which also
+/// flushes the TLB.
+///
+/// This is unsafe because the new table must map the kernel's code, stack and
+/// data at the same addresses as the current one.
+pub unsafe fn write_cr3(address: PhysicalAddr) {
+	asm!("mov $0, %cr3" :: "r"(address) : "memory" : "volatile");
+}
+
+/// Writes the current value of cr3 back to itself, which flushes every
+/// (non-global) entry in the TLB. The whole value is written back, so the
+/// caching flags and PCID stay the same.
+pub fn reload_cr3() {
+	unsafe { write_cr3(read_cr3_raw()) };
+}
+
+/// Invalidates the TLB entry for the page containing the given virtual
+/// address, so that the CPU re-reads the page tables the next time the page is
+/// accessed.
+///
+/// This must be called after modifying or removing the page table entry for
+/// a page that was previously mapped.
+pub fn flush_tlb(addr: VirtualAddr) {
+	unsafe {
+		asm!("invlpg ($0)" :: "r"(addr) : "memory" : "volatile");
+	}
+}
+
+/// Invalidates every (non-global) entry in the TLB.
+pub fn flush_all() {
+	reload_cr3();
+}
+
+/// Returns the contents of the cr2 register, which holds the virtual address
+/// that caused the most recent page fault.
+pub fn read_cr2() -> VirtualAddr {
+	let address: usize;
+	unsafe { asm!("mov %cr2, $0" : "=r"(address) ::: "volatile") };
+	address
+}
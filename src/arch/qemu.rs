@@ -0,0 +1,32 @@
+
+//
+//  QEMU Support
+//
+
+use arch::cpu::halt_loop;
+use arch::io::outl;
+
+/// The IO port of QEMU's `isa-debug-exit` device, set with the `iobase` option
+/// in `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// The exit codes we can pass to `qemu_exit`.
+///
+/// QEMU exits with the status `(code << 1) | 1`, so that a kernel can never
+/// make QEMU look like it exited successfully with status 0. `Success` makes
+/// QEMU exit with status 33, and `Failed` with 35.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+	Success = 0x10,
+	Failed = 0x11,
+}
+
+/// Exits QEMU with the given exit code.
+///
+/// This only works if QEMU was started with the `isa-debug-exit` device. If
+/// it wasn't (or we're not running under QEMU), the CPU is halted instead.
+pub fn qemu_exit(code: QemuExitCode) -> ! {
+	unsafe { outl(DEBUG_EXIT_PORT, code as u32) };
+	halt_loop();
+}
@@ -0,0 +1,85 @@
+
+//
+//  Time Stamp Counter
+//
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use driver::pit;
+
+/// The number of PIT ticks we measure the TSC over when calibrating it.
+const CALIBRATION_TICKS: usize = 50;
+
+/// The number of TSC cycles per millisecond, measured by `calibrate`, or 0 if
+/// the TSC hasn't been calibrated yet.
+static CYCLES_PER_MS: AtomicUsize = AtomicUsize::new(0);
+
+/// The TSC's value when calibration finished, and the time since the PIT was
+/// started at that moment, in nanoseconds. `now_ns` measures the TSC from
+/// here, so it counts from the same zero as the PIT.
+static BASE_TSC: AtomicUsize = AtomicUsize::new(0);
+static BASE_NS: AtomicUsize = AtomicUsize::new(0);
+
+/// Reads the time stamp counter (TSC), which counts CPU cycles since the CPU
+/// was reset.
+pub fn read_tsc() -> u64 {
+	// The `rdtsc` instruction splits the counter across `edx` (high 32 bits)
+	// and `eax` (low 32 bits)
+	let high: u32;
+	let low: u32;
+	unsafe {
+		asm!("rdtsc" : "={eax}"(low), "={edx}"(high) ::: "volatile");
+	}
+	(high as u64) << 32 | low as u64
+}
+
+/// Measures how fast the TSC runs by counting cycles across a known number of
+/// PIT ticks.
+///
+/// The PIT must be running and interrupts must be enabled, otherwise this
+/// never returns.
+pub fn calibrate() {
+	// Wait for the next tick first, so we start measuring on a tick boundary
+	pit::wait_ticks(1);
+	let start = read_tsc();
+	pit::wait_ticks(CALIBRATION_TICKS);
+	let end = read_tsc();
+
+	let ms = CALIBRATION_TICKS as u64 * 1000 / pit::TIMER_FREQUENCY as u64;
+
+	// Store the base before the rate, since `now_ns` only uses the base once
+	// it sees a non-zero rate
+	BASE_TSC.store(end as usize, Ordering::SeqCst);
+	BASE_NS.store(pit_ns() as usize, Ordering::SeqCst);
+	CYCLES_PER_MS.store(((end - start) / ms) as usize, Ordering::SeqCst);
+}
+
+/// Returns the number of nanoseconds since the PIT was started, to the
+/// precision of one of its ticks.
+fn pit_ns() -> u64 {
+	pit::ticks() as u64 * 1_000_000_000 / pit::TIMER_FREQUENCY as u64
+}
+
+/// Returns the number of nanoseconds since the PIT was started, derived from
+/// the TSC.
+///
+/// This is a best effort estimate, adequate for profiling how long things
+/// take. The calibration is only approximate, and on older CPUs the TSC's
+/// frequency changes with the CPU's power state. If the TSC hasn't been
+/// calibrated yet, this falls back to the PIT's (much coarser) tick count.
+/// Both count from the same zero, so the time doesn't jump when calibration
+/// finishes.
+pub fn now_ns() -> u64 {
+	let cycles_per_ms = CYCLES_PER_MS.load(Ordering::SeqCst) as u64;
+	if cycles_per_ms == 0 {
+		return pit_ns();
+	}
+
+	// Convert whole milliseconds and the remainder separately, so the
+	// multiplication can't overflow
+	let cycles = read_tsc() - BASE_TSC.load(Ordering::SeqCst) as u64;
+	let ms = cycles / cycles_per_ms;
+	let remainder = cycles % cycles_per_ms;
+	let elapsed = ms * 1_000_000 + remainder * 1_000_000 / cycles_per_ms;
+	BASE_NS.load(Ordering::SeqCst) as u64 + elapsed
+}
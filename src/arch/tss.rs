@@ -0,0 +1,46 @@
+
+//
+//  Task State Segment
+//
+
+/// In long mode, the Task State Segment (TSS) no longer stores the state of
+/// tasks for hardware task switching. Instead, it holds the stacks the CPU
+/// switches to when an interrupt arrives.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+	reserved_1: u32,
+
+	/// The stack pointers loaded when switching to a more privileged ring (0,
+	/// 1, or 2) during an interrupt.
+	pub privilege_stack_table: [u64; 3],
+
+	reserved_2: u64,
+
+	/// The Interrupt Stack Table (IST). An IDT entry can name one of these 7
+	/// stack pointers to switch to before calling its handler, regardless of
+	/// the current privilege level.
+	pub interrupt_stack_table: [u64; 7],
+
+	reserved_3: u64,
+	reserved_4: u16,
+
+	/// The offset from the start of the TSS to the IO permission bitmap.
+	pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+	/// Create a new TSS with every stack pointer set to 0, and no IO permission
+	/// bitmap.
+	pub const fn new() -> TaskStateSegment {
+		TaskStateSegment {
+			reserved_1: 0,
+			privilege_stack_table: [0; 3],
+			reserved_2: 0,
+			interrupt_stack_table: [0; 7],
+			reserved_3: 0,
+			reserved_4: 0,
+			iomap_base: 0,
+		}
+	}
+}
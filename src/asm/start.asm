@@ -330,6 +330,10 @@ setup_sse:
 	or ax, 3 << 9
 	mov cr4, eax
 
+	; Put the FPU into a known state, since the bootloader could've left
+	; anything in its registers
+	fninit
+
 	ret
 
 	; Error handling, in case SSE isn't supported
@@ -392,6 +396,10 @@ long_mode:
 	mov rax, KERNEL_BASE
 	add rsp, rax
 
+	; Clear the frame pointer, so that a backtrace knows to stop when it
+	; reaches `kernel_main`
+	xor rbp, rbp
+
 	; Call into the Rust code's main function
 	extern kernel_main
 	call kernel_main
@@ -0,0 +1,212 @@
+
+//
+//  Debugging Helpers
+//
+
+use core::fmt;
+use core::slice;
+
+/// The most stack frames a backtrace prints, in case the chain of frame
+/// pointers is corrupted and loops back on itself.
+const MAX_BACKTRACE_FRAMES: usize = 32;
+
+// Symbols defined by the linker script, marking where parts of the kernel are
+// in virtual memory. Only their addresses are meaningful.
+extern {
+	static kernel_start: u8;
+	static kernel_text_start: u8;
+	static kernel_rodata_start: u8;
+	static kernel_end: u8;
+}
+
+/// The number of bytes shown on each row of a hex dump.
+const HEX_DUMP_ROW_SIZE: usize = 16;
+
+/// A single row of a hex dump, formatted as an offset, the hex value of each
+/// byte, and the bytes as ASCII characters.
+struct HexDumpRow<'a> {
+	/// The offset of the row's first byte from the start of the dump.
+	offset: usize,
+
+	/// The bytes in the row. Only the last row can have fewer than
+	/// `HEX_DUMP_ROW_SIZE` bytes.
+	bytes: &'a [u8],
+}
+
+impl<'a> fmt::Display for HexDumpRow<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:08x}: ", self.offset)?;
+
+		// Pad the hex column of a short row with spaces, so the ASCII column
+		// lines up with the rows above it
+		for i in 0 .. HEX_DUMP_ROW_SIZE {
+			match self.bytes.get(i) {
+				Some(byte) => write!(f, "{:02x} ", byte)?,
+				None => write!(f, "   ")?,
+			}
+		}
+
+		write!(f, "| ")?;
+		for &byte in self.bytes {
+			// Only print characters that won't mess up the terminal
+			let character = if byte >= 0x20 && byte < 0x7f { byte } else { b'.' };
+			write!(f, "{}", character as char)?;
+		}
+		Ok(())
+	}
+}
+
+/// Prints the contents of a region of memory as a hex dump to the terminal and
+/// serial port, 16 bytes per row.
+///
+/// This is unsafe because the whole region must be mapped and readable.
+pub unsafe fn hex_dump(start: usize, length: usize) {
+	let bytes = slice::from_raw_parts(start as *const u8, length);
+
+	println!("hex dump of {} bytes at {:#x}:", length, start);
+	serial_println!("hex dump of {} bytes at {:#x}:", length, start);
+	for (i, row) in bytes.chunks(HEX_DUMP_ROW_SIZE).enumerate() {
+		let row = HexDumpRow {
+			offset: i * HEX_DUMP_ROW_SIZE,
+			bytes: row,
+		};
+		println!("{}", row);
+		serial_println!("{}", row);
+	}
+}
+
+/// A snapshot of the general purpose registers.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct Registers {
+	pub rax: u64,
+	pub rbx: u64,
+	pub rcx: u64,
+	pub rdx: u64,
+	pub rsi: u64,
+	pub rdi: u64,
+	pub rbp: u64,
+	pub rsp: u64,
+	pub r8: u64,
+	pub r9: u64,
+	pub r10: u64,
+	pub r11: u64,
+	pub r12: u64,
+	pub r13: u64,
+	pub r14: u64,
+	pub r15: u64,
+}
+
+impl Registers {
+	/// Captures the values of the general purpose registers at the point this
+	/// is called.
+	///
+	/// This is a best effort snapshot: the compiler is free to have changed
+	/// any register before we get here, and one register is used to hold the
+	/// address we're writing the snapshot to. It's still useful for seeing
+	/// the rough state of the CPU after a fatal error.
+	#[inline(always)]
+	pub fn capture() -> Registers {
+		let mut registers = Registers::default();
+		unsafe {
+			asm!("mov %rax, 0($0)
+				mov %rbx, 8($0)
+				mov %rcx, 16($0)
+				mov %rdx, 24($0)
+				mov %rsi, 32($0)
+				mov %rdi, 40($0)
+				mov %rbp, 48($0)
+				mov %rsp, 56($0)
+				mov %r8, 64($0)
+				mov %r9, 72($0)
+				mov %r10, 80($0)
+				mov %r11, 88($0)
+				mov %r12, 96($0)
+				mov %r13, 104($0)
+				mov %r14, 112($0)
+				mov %r15, 120($0)"
+				:: "r"(&mut registers as *mut Registers)
+				: "memory"
+				: "volatile");
+		}
+		registers
+	}
+
+	/// Prints the registers to the terminal and serial port.
+	pub fn dump(&self) {
+		println!("{}", self);
+		serial_println!("{}", self);
+	}
+}
+
+impl fmt::Display for Registers {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// Print 3 registers per row, so each row fits on the terminal
+		let registers = [
+			("rax", self.rax), ("rbx", self.rbx), ("rcx", self.rcx),
+			("rdx", self.rdx), ("rsi", self.rsi), ("rdi", self.rdi),
+			("rbp", self.rbp), ("rsp", self.rsp), ("r8", self.r8),
+			("r9", self.r9), ("r10", self.r10), ("r11", self.r11),
+			("r12", self.r12), ("r13", self.r13), ("r14", self.r14),
+			("r15", self.r15),
+		];
+		for (i, &(name, value)) in registers.iter().enumerate() {
+			if i > 0 {
+				write!(f, "{}", if i % 3 == 0 { "\n" } else { "  " })?;
+			}
+			write!(f, "{:>3}: {:#018x}", name, value)?;
+		}
+		Ok(())
+	}
+}
+
+/// Prints the return address of each function on the call stack to the
+/// terminal and serial port, starting with the caller of this function. The
+/// addresses can be turned into function names and line numbers later using
+/// `addr2line` on the kernel binary.
+///
+/// This works by following the chain of saved frame pointers (rbp), so it
+/// relies on the kernel being compiled with frame pointers. The walk stops at
+/// the first frame that isn't in the kernel image (where all the kernel's
+/// stacks are), or whose return address isn't in the kernel's code. This
+/// includes the frame of an exception handler for an exception with an error
+/// code, since the error code sits where the return address would be.
+pub fn backtrace() {
+	let mut frame: usize;
+	unsafe { asm!("mov %rbp, $0" : "=r"(frame) ::: "volatile") };
+
+	let (image_start, image_end, text_start, text_end) = unsafe {
+		(&kernel_start as *const u8 as usize,
+			&kernel_end as *const u8 as usize,
+			&kernel_text_start as *const u8 as usize,
+			&kernel_rodata_start as *const u8 as usize)
+	};
+
+	println!("backtrace:");
+	serial_println!("backtrace:");
+	for i in 0 .. MAX_BACKTRACE_FRAMES {
+		// Each frame holds the caller's frame pointer followed by the return
+		// address, which must both be in the kernel image. A corrupt frame
+		// pointer can be anything, so make sure the check can't overflow
+		let in_image = frame >= image_start
+			&& frame <= image_end.saturating_sub(16);
+		if frame % 8 != 0 || !in_image {
+			break;
+		}
+
+		let return_address = unsafe { *((frame + 8) as *const usize) };
+		if return_address < text_start || return_address >= text_end {
+			break;
+		}
+		println!("{:>4}: {:#018x}", i, return_address);
+		serial_println!("{:>4}: {:#018x}", i, return_address);
+
+		// The stack grows down, so the caller's frame must be above this one.
+		// Stopping otherwise also makes sure we can't go around in a cycle
+		let next = unsafe { *(frame as *const usize) };
+		if next <= frame {
+			break;
+		}
+		frame = next;
+	}
+}
@@ -0,0 +1,243 @@
+
+//
+//  Advanced Programmable Interrupt Controller Driver
+//
+
+use core::ptr;
+
+use arch::cpuid::has_apic;
+use arch::idt::ExceptionStackFrame;
+use arch::interrupts::set_interrupt_handler;
+use arch::msr::read_msr;
+use arch::paging::{PhysicalAddr, VirtualAddr};
+use driver::pic;
+use sync::IrqMutex;
+
+/// The MSR holding the local APIC's physical base address.
+const APIC_BASE_MSR: u32 = 0x1b;
+
+/// The bits in `APIC_BASE_MSR` holding the local APIC's (page aligned)
+/// physical base address.
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The offsets of the local APIC's registers from its base address. Each
+/// register is 32 bits, aligned to 16 bytes.
+const LAPIC_ID: usize = 0x020;
+const LAPIC_EOI: usize = 0x0b0;
+const LAPIC_SPURIOUS: usize = 0x0f0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: usize = 0x380;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+/// The bit in the spurious interrupt vector register that enables the local
+/// APIC.
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+
+/// The vector that spurious interrupts are delivered on. Its lowest 4 bits
+/// must be set on older CPUs.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// The bit in a local vector table (LVT) entry that masks the interrupt.
+const LVT_MASKED: u32 = 1 << 16;
+
+/// The bit in the timer's LVT entry that makes it fire repeatedly, rather than
+/// once.
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+
+/// The timer divide configuration that divides the APIC's bus clock by 16.
+const TIMER_DIVIDE_BY_16: u32 = 0x3;
+
+/// The IO APIC's register select and data window registers, as offsets from
+/// its base address. Every other register is accessed indirectly through
+/// these two.
+const IOAPIC_REGISTER_SELECT: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+
+/// The IO APIC's version register, whose bits 16 to 23 hold the index of its
+/// last redirection table entry.
+const IOAPIC_VERSION: u32 = 0x01;
+
+/// The index of the first IO APIC register holding the redirection table.
+/// Each entry is 64 bits, split across two registers.
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+/// The bit in a redirection table entry that masks its IRQ.
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+/// The bit offset in a redirection table entry of the destination local
+/// APIC's ID.
+const REDIRECTION_DESTINATION_SHIFT: u64 = 56;
+
+/// The local APIC, once it's been enabled by `init`. Interrupts are disabled
+/// while it's locked, since IRQ handlers lock it to signal the end of their
+/// interrupt.
+pub static LOCAL_APIC: IrqMutex<Option<LocalApic>> = IrqMutex::new(None);
+
+/// The IO APIC, once it's been set up by `init`.
+pub static IO_APIC: IrqMutex<Option<IoApic>> = IrqMutex::new(None);
+
+/// Returns the physical address of the local APIC's registers. The page
+/// containing them must be mapped with `ENTRY_MMIO` before `init` is called.
+pub fn local_apic_physical_base() -> PhysicalAddr {
+	let base = unsafe { read_msr(APIC_BASE_MSR) };
+	(base & APIC_BASE_ADDRESS_MASK) as PhysicalAddr
+}
+
+/// The local APIC, which receives interrupts for a single CPU core.
+pub struct LocalApic {
+	/// The virtual address its registers are mapped at.
+	base: VirtualAddr,
+}
+
+impl LocalApic {
+	/// Reads one of the local APIC's registers.
+	fn read(&self, register: usize) -> u32 {
+		let address = (self.base + register) as *const u32;
+		unsafe { ptr::read_volatile(address) }
+	}
+
+	/// Writes to one of the local APIC's registers.
+	fn write(&mut self, register: usize, value: u32) {
+		let address = (self.base + register) as *mut u32;
+		unsafe { ptr::write_volatile(address, value) };
+	}
+
+	/// Returns the APIC ID of the CPU this local APIC belongs to.
+	pub fn id(&self) -> u8 {
+		(self.read(LAPIC_ID) >> 24) as u8
+	}
+
+	/// Enables the local APIC, with spurious interrupts delivered on
+	/// `SPURIOUS_VECTOR`.
+	fn enable(&mut self) {
+		let value = SPURIOUS_APIC_ENABLE | SPURIOUS_VECTOR as u32;
+		self.write(LAPIC_SPURIOUS, value);
+	}
+
+	/// Tells the local APIC we've finished handling its interrupt, so it can
+	/// send us another. Must be called at the end of every IRQ handler
+	/// (except for spurious interrupts).
+	pub fn end_of_interrupt(&mut self) {
+		self.write(LAPIC_EOI, 0);
+	}
+
+	/// Starts the APIC timer, which fires an interrupt on the given vector
+	/// every `count` ticks of the APIC's bus clock divided by 16.
+	///
+	/// The bus clock's frequency varies between machines, so the count needs
+	/// to be calibrated (eg. against the PIT) to get a particular rate.
+	pub fn start_timer(&mut self, vector: u8, count: u32) {
+		self.write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+		self.write(LAPIC_LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+		self.write(LAPIC_TIMER_INITIAL_COUNT, count);
+	}
+
+	/// Stops the APIC timer.
+	pub fn stop_timer(&mut self) {
+		self.write(LAPIC_LVT_TIMER, LVT_MASKED);
+		self.write(LAPIC_TIMER_INITIAL_COUNT, 0);
+	}
+}
+
+/// Called when the local APIC delivers a spurious interrupt, ie. one that was
+/// withdrawn before the CPU accepted it. There's nothing to handle, and the
+/// local APIC mustn't be sent an end of interrupt for it, so just return.
+extern "x86-interrupt" fn spurious_handler(_frame: &mut ExceptionStackFrame) {}
+
+/// The IO APIC, which routes IRQs from devices to the local APICs.
+pub struct IoApic {
+	/// The virtual address its registers are mapped at.
+	base: VirtualAddr,
+}
+
+impl IoApic {
+	/// Selects the register that the data window reads from and writes to.
+	fn select(&mut self, register: u32) {
+		let address = (self.base + IOAPIC_REGISTER_SELECT) as *mut u32;
+		unsafe { ptr::write_volatile(address, register) };
+	}
+
+	/// Reads one of the IO APIC's registers.
+	fn read(&mut self, register: u32) -> u32 {
+		self.select(register);
+		let address = (self.base + IOAPIC_WINDOW) as *const u32;
+		unsafe { ptr::read_volatile(address) }
+	}
+
+	/// Writes to one of the IO APIC's registers.
+	fn write(&mut self, register: u32, value: u32) {
+		self.select(register);
+		let address = (self.base + IOAPIC_WINDOW) as *mut u32;
+		unsafe { ptr::write_volatile(address, value) };
+	}
+
+	/// Returns the number of IRQs the IO APIC handles.
+	pub fn irq_count(&mut self) -> u8 {
+		((self.read(IOAPIC_VERSION) >> 16) as u8).saturating_add(1)
+	}
+
+	/// Writes an IRQ's 64 bit redirection table entry.
+	fn set_redirection(&mut self, irq: u8, entry: u64) {
+		let register = IOAPIC_REDIRECTION_TABLE + irq as u32 * 2;
+		self.write(register, entry as u32);
+		self.write(register + 1, (entry >> 32) as u32);
+	}
+
+	/// Routes an IRQ to an interrupt vector on the local APIC with the given
+	/// ID, and unmasks it.
+	///
+	/// The IO APIC's IRQs are numbered the same as the PIC's for ISA devices,
+	/// unless the ACPI tables give an override.
+	pub fn route(&mut self, irq: u8, vector: u8, apic_id: u8) {
+		let destination = (apic_id as u64) << REDIRECTION_DESTINATION_SHIFT;
+		self.set_redirection(irq, destination | vector as u64);
+	}
+
+	/// Masks an IRQ, so it isn't delivered to any local APIC.
+	pub fn mask(&mut self, irq: u8) {
+		self.set_redirection(irq, REDIRECTION_MASKED);
+	}
+
+	/// Masks every IRQ.
+	fn mask_all(&mut self) {
+		for irq in 0 .. self.irq_count() {
+			self.mask(irq);
+		}
+	}
+}
+
+
+/// Initialise the APIC module.
+///
+/// Masks every IRQ on the legacy PICs, enables the local APIC, and masks every
+/// IRQ on the IO APIC. Drivers should then route the IRQs they handle with
+/// `IoApic::route`, and signal the end of each interrupt to the local APIC
+/// rather than the PICs. Returns false (leaving the PICs alone) if the CPU
+/// doesn't have an APIC.
+///
+/// This is unsafe because the local APIC's registers (at
+/// `local_apic_physical_base`) and the IO APIC's registers (given by the ACPI
+/// MADT table, usually at 0xfec00000) must already be mapped at the given
+/// virtual addresses, using `ENTRY_MMIO` so they aren't cached.
+pub unsafe fn init(local_apic_base: VirtualAddr, io_apic_base: VirtualAddr)
+		-> bool {
+	if !has_apic() {
+		return false;
+	}
+
+	// Stop the PICs from delivering any interrupts before we switch over
+	pic::disable();
+
+	// The local APIC can deliver spurious interrupts as soon as it's enabled,
+	// so their vector needs a handler first
+	set_interrupt_handler(SPURIOUS_VECTOR, spurious_handler);
+
+	let mut local_apic = LocalApic { base: local_apic_base };
+	local_apic.enable();
+	*LOCAL_APIC.lock() = Some(local_apic);
+
+	let mut io_apic = IoApic { base: io_apic_base };
+	io_apic.mask_all();
+	*IO_APIC.lock() = Some(io_apic);
+	true
+}
@@ -0,0 +1,160 @@
+
+//
+//  Console Output
+//
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use driver::framebuffer;
+use driver::serial;
+use driver::vga::{self, Color};
+
+/// A device that text can be printed to in a terminal-style fashion. The
+/// `print!` family of macros writes to whichever console is currently
+/// selected, so the rest of the kernel doesn't need to care what the output
+/// device is.
+pub trait Console: fmt::Write {
+	/// Sets the foreground and background color used for all characters
+	/// written after this call.
+	fn set_color(&mut self, foreground: Color, background: Color);
+
+	/// Returns the current foreground and background color as a
+	/// `(foreground, background)` pair.
+	fn color(&self) -> (Color, Color);
+
+	/// Clears the console to the current background color and moves the
+	/// cursor to the top left.
+	fn clear(&mut self);
+
+	/// Moves the cursor to the given position, in characters.
+	fn set_cursor(&mut self, x: usize, y: usize);
+
+	/// Puts the console back into a known state, with white text on black and
+	/// an empty screen.
+	fn reset(&mut self) {
+		self.set_color(Color::White, Color::Black);
+		self.clear();
+	}
+
+	/// Makes sure everything written so far is visible. Consoles that write
+	/// directly to their device don't need to do anything.
+	fn flush(&mut self) {}
+}
+
+/// The devices that the kernel's console output can be sent to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Output {
+	/// The VGA text mode buffer, which is always available on a PC.
+	Vga,
+
+	/// The framebuffer console, which must be set up with `framebuffer::init`
+	/// first.
+	Framebuffer,
+
+	/// The COM1 serial port.
+	Serial,
+}
+
+/// The output device that's currently selected, stored as an `Output` cast to
+/// an integer. It's an atomic rather than a lock so that printing from an
+/// interrupt handler can't deadlock on it. VGA is the default, so printing
+/// works without any setup.
+static OUTPUT: AtomicUsize = AtomicUsize::new(Output::Vga as usize);
+
+/// Selects the device that the `print!` family of macros writes to.
+///
+/// Choosing the framebuffer based on the boot environment needs the
+/// bootloader's framebuffer information (in the multiboot info struct), which
+/// we don't parse yet, so for now this has to be called explicitly.
+pub fn select(output: Output) {
+	OUTPUT.store(output as usize, Ordering::SeqCst);
+}
+
+/// Returns the device that's currently selected.
+pub fn output() -> Output {
+	match OUTPUT.load(Ordering::SeqCst) {
+		value if value == Output::Framebuffer as usize => Output::Framebuffer,
+		value if value == Output::Serial as usize => Output::Serial,
+		_ => Output::Vga,
+	}
+}
+
+/// Locks the currently selected console and calls the given function with it,
+/// returning its result.
+///
+/// If the framebuffer is selected but hasn't been set up, the VGA console is
+/// used instead, so output is never lost.
+pub fn with_console<F, R>(function: F) -> R where F: FnOnce(&mut Console) -> R {
+	match output() {
+		Output::Serial => function(&mut *serial::COM1.lock()),
+		Output::Framebuffer => {
+			let mut console = framebuffer::CONSOLE.lock();
+			if let Some(ref mut console) = *console {
+				return function(console);
+			}
+
+			function(&mut *vga::WRITER.lock())
+		},
+		Output::Vga => function(&mut *vga::WRITER.lock()),
+	}
+}
+
+
+/// A macro to print a format string and arguments to the console.
+macro_rules! print {
+    ($($arg:tt)*) => ({
+        $crate::driver::console::print(format_args!($($arg)*));
+    });
+}
+
+/// Prints a string to the console, appending a newline after it.
+macro_rules! println {
+    ($fmt:expr) => (print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// A macro to print a format string and arguments to the console in the
+/// given foreground and background colors.
+macro_rules! color_print {
+    ($fg:expr, $bg:expr, $($arg:tt)*) => ({
+        $crate::driver::console::print_colored($fg, $bg, format_args!($($arg)*));
+    });
+}
+
+/// Prints a string to the console in the given foreground and background
+/// colors, appending a newline after it.
+macro_rules! color_println {
+    ($fg:expr, $bg:expr, $fmt:expr) => (color_print!($fg, $bg, concat!($fmt, "\n")));
+    ($fg:expr, $bg:expr, $fmt:expr, $($arg:tt)*) => (color_print!($fg, $bg, concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Prints a series of format arguments to the console.
+pub fn print(args: fmt::Arguments) {
+	// This is required (instead of just inlining this in the `print!` macro) to
+	// avoid a deadlock of the spin mutex around the console. Eg. in the code
+	// `println!("something {}", { println!("else"); 3 })`, we'd call the
+	// console's `lock()` function twice, causing a deadlock. By moving the call
+	// to the mutex's lock function into a separate function, we avoid this.
+	use core::fmt::Write;
+	with_console(|console| {
+		console.write_fmt(args).unwrap();
+		console.flush();
+	});
+}
+
+/// Prints a series of format arguments to the console in the given foreground
+/// and background colors, then restores the console's previous color.
+pub fn print_colored(foreground: Color, background: Color, args: fmt::Arguments) {
+	use core::fmt::Write;
+	with_console(|console| {
+		// Restore the previous color before checking the result of the write,
+		// so that the color is put back even if formatting fails
+		let (previous_foreground, previous_background) = console.color();
+		console.set_color(foreground, background);
+		let result = console.write_fmt(args);
+		console.set_color(previous_foreground, previous_background);
+		console.flush();
+		result.unwrap();
+	});
+}
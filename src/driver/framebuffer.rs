@@ -0,0 +1,287 @@
+
+//
+//  Framebuffer Console
+//
+
+use core::fmt;
+use core::ptr;
+
+use arch::paging::VirtualAddr;
+use driver::console::Console;
+use driver::font::{self, GLYPH_WIDTH, GLYPH_HEIGHT};
+use driver::vga::Color;
+use sync::IrqMutex;
+
+/// The RGB value of each VGA color, indexed by the color's number, so that the
+/// console looks the same as the VGA text mode.
+const PALETTE: [u32; 16] = [
+	0x000000, 0x0000aa, 0x00aa00, 0x00aaaa,
+	0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+	0x555555, 0x5555ff, 0x55ff55, 0x55ffff,
+	0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+];
+
+/// The framebuffer console, if one has been set up with `init`. Interrupts are
+/// disabled while it's locked, like the VGA writer.
+pub static CONSOLE: IrqMutex<Option<FramebufferConsole>> = IrqMutex::new(None);
+
+/// Writes text to a linear framebuffer (eg. one set up by the bootloader) in a
+/// terminal-style fashion, by drawing each character from a bitmap font. It
+/// works the same way as the VGA `Writer`, for when the VGA text mode isn't
+/// available.
+///
+/// Only 24 and 32 bit pixels are supported, with red in bits 16 to 23, green
+/// in bits 8 to 15, and blue in bits 0 to 7 (the usual layout). Unlike the VGA
+/// writer, ANSI escape sequences aren't interpreted.
+pub struct FramebufferConsole {
+	/// The virtual address of the framebuffer's first pixel.
+	address: VirtualAddr,
+
+	/// The number of bytes between the start of each row of pixels.
+	pitch: usize,
+
+	/// The number of bytes used by each pixel.
+	bytes_per_pixel: usize,
+
+	/// The size of the console, in character cells. Both are always at least
+	/// 1, since `new` rejects framebuffers too small for a single character.
+	columns: usize,
+	rows: usize,
+
+	/// The cursor's position, in character cells. Like the VGA writer, `x` can
+	/// be one past the end of the line, in which case we wrap when the next
+	/// character is written.
+	x: usize,
+	y: usize,
+
+	/// The colors characters are drawn in.
+	foreground: Color,
+	background: Color,
+}
+
+impl FramebufferConsole {
+	/// Create a new console for the framebuffer at the given address, whose
+	/// size is given in pixels.
+	///
+	/// Returns `None` if the framebuffer doesn't use 24 or 32 bit pixels, or
+	/// is too small to fit a single character.
+	///
+	/// This is unsafe because the address must point to a valid framebuffer
+	/// of the given size, which the console has exclusive access to. It should
+	/// be mapped with `ENTRY_MMIO`, so writes to it aren't cached.
+	pub unsafe fn new(address: VirtualAddr, width: usize, height: usize,
+			pitch: usize, bits_per_pixel: usize) -> Option<FramebufferConsole> {
+		if bits_per_pixel != 24 && bits_per_pixel != 32 {
+			return None;
+		}
+
+		let columns = width / GLYPH_WIDTH;
+		let rows = height / GLYPH_HEIGHT;
+		if columns == 0 || rows == 0 {
+			return None;
+		}
+
+		Some(FramebufferConsole {
+			address: address,
+			pitch: pitch,
+			bytes_per_pixel: bits_per_pixel / 8,
+			columns: columns,
+			rows: rows,
+			x: 0,
+			y: 0,
+			foreground: Color::White,
+			background: Color::Black,
+		})
+	}
+
+	/// Returns the size of the console as a `(columns, rows)` pair, in
+	/// character cells.
+	pub fn dimensions(&self) -> (usize, usize) {
+		(self.columns, self.rows)
+	}
+
+	/// Sets the foreground and background color used for all characters
+	/// written after this call.
+	pub fn set_color(&mut self, foreground: Color, background: Color) {
+		self.foreground = foreground;
+		self.background = background;
+	}
+
+	/// Sets the cursor's position, in character cells. Positions outside the
+	/// console are clamped to its edges.
+	pub fn set_cursor(&mut self, x: usize, y: usize) {
+		self.x = x.min(self.columns - 1);
+		self.y = y.min(self.rows - 1);
+	}
+
+	/// Returns the cursor's position as an `(x, y)` pair, in character cells.
+	pub fn cursor_position(&self) -> (usize, usize) {
+		(self.x, self.y)
+	}
+
+	/// Clears the entire console to the current background color.
+	pub fn clear_screen(&mut self) {
+		for row in 0 .. self.rows {
+			self.clear_row(row);
+		}
+	}
+
+	/// Clears a single row of character cells to the current background color.
+	fn clear_row(&mut self, row: usize) {
+		let background = PALETTE[self.background as usize];
+		for y in (row * GLYPH_HEIGHT) .. ((row + 1) * GLYPH_HEIGHT) {
+			for x in 0 .. (self.columns * GLYPH_WIDTH) {
+				self.put_pixel(x, y, background);
+			}
+		}
+	}
+
+	/// Sets the pixel at the given position to an RGB color.
+	fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+		let address = self.address + y * self.pitch + x * self.bytes_per_pixel;
+		unsafe {
+			if self.bytes_per_pixel == 4 {
+				ptr::write_volatile(address as *mut u32, color);
+			} else {
+				// 24 bit pixels are stored in little endian order, ie. blue
+				// first
+				let bytes = address as *mut u8;
+				ptr::write_volatile(bytes, color as u8);
+				ptr::write_volatile(bytes.offset(1), (color >> 8) as u8);
+				ptr::write_volatile(bytes.offset(2), (color >> 16) as u8);
+			}
+		}
+	}
+
+	/// Draws a character in the character cell at the given position, using
+	/// the current colors.
+	fn draw_character(&mut self, column: usize, row: usize, character: u8) {
+		let glyph = font::glyph(character);
+		let foreground = PALETTE[self.foreground as usize];
+		let background = PALETTE[self.background as usize];
+		for (glyph_y, bits) in glyph.iter().enumerate() {
+			let y = row * GLYPH_HEIGHT + glyph_y;
+			for glyph_x in 0 .. GLYPH_WIDTH {
+				let x = column * GLYPH_WIDTH + glyph_x;
+				let color = if bits & (0x80 >> glyph_x) != 0 {
+					foreground
+				} else {
+					background
+				};
+				self.put_pixel(x, y, color);
+			}
+		}
+	}
+
+	/// Writes a single character at the cursor and advances it, handling
+	/// `\n`, `\r` and backspace the same way as the VGA writer.
+	fn write_byte(&mut self, character: u8) {
+		match character {
+			b'\n' => self.newline(),
+			b'\r' => self.x = 0,
+			0x08 => self.backspace(),
+			_ => {
+				// Wrap onto the next line if the previous character filled the
+				// last cell of this one
+				if self.x >= self.columns {
+					self.newline();
+				}
+
+				let (x, y) = (self.x, self.y);
+				self.draw_character(x, y, character);
+				self.x += 1;
+			},
+		}
+	}
+
+	/// Moves the cursor back one cell and clears the cell it lands on.
+	fn backspace(&mut self) {
+		if self.x > 0 {
+			self.x -= 1;
+		} else if self.y > 0 {
+			self.y -= 1;
+			self.x = self.columns - 1;
+		} else {
+			return;
+		}
+
+		let (x, y) = (self.x, self.y);
+		self.draw_character(x, y, b' ');
+	}
+
+	/// Moves the cursor to the start of the next line, scrolling the console
+	/// up if the cursor is on the last line.
+	fn newline(&mut self) {
+		if self.y + 1 >= self.rows {
+			self.scroll_up();
+		} else {
+			self.y += 1;
+		}
+		self.x = 0;
+	}
+
+	/// Moves every row of characters up by one, and clears the bottom row.
+	fn scroll_up(&mut self) {
+		// Move all the pixel rows below the first row of characters up in one
+		// go
+		let row_bytes = self.pitch * GLYPH_HEIGHT;
+		unsafe {
+			ptr::copy((self.address + row_bytes) as *const u8,
+				self.address as *mut u8, row_bytes * (self.rows - 1));
+		}
+
+		let bottom = self.rows - 1;
+		self.clear_row(bottom);
+	}
+}
+
+impl fmt::Write for FramebufferConsole {
+	fn write_str(&mut self, string: &str) -> fmt::Result {
+		for byte in string.bytes() {
+			self.write_byte(byte);
+		}
+		Ok(())
+	}
+}
+
+impl Console for FramebufferConsole {
+	fn set_color(&mut self, foreground: Color, background: Color) {
+		FramebufferConsole::set_color(self, foreground, background);
+	}
+
+	fn color(&self) -> (Color, Color) {
+		(self.foreground, self.background)
+	}
+
+	fn clear(&mut self) {
+		self.clear_screen();
+		self.set_cursor(0, 0);
+	}
+
+	fn set_cursor(&mut self, x: usize, y: usize) {
+		FramebufferConsole::set_cursor(self, x, y);
+	}
+}
+
+
+/// Sets up the framebuffer console for the framebuffer at the given address,
+/// and clears it. It still has to be selected with `console::select` before
+/// anything is printed to it.
+///
+/// Returns false (leaving any existing console alone) if the framebuffer can't
+/// be used, for the same reasons as `FramebufferConsole::new`.
+///
+/// This is unsafe for the same reasons as `FramebufferConsole::new`.
+pub unsafe fn init(address: VirtualAddr, width: usize, height: usize,
+		pitch: usize, bits_per_pixel: usize) -> bool {
+	let console = FramebufferConsole::new(address, width, height, pitch,
+		bits_per_pixel);
+	match console {
+		Some(mut console) => {
+			console.clear_screen();
+			*CONSOLE.lock() = Some(console);
+			true
+		},
+		None => false,
+	}
+}
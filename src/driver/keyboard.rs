@@ -0,0 +1,291 @@
+
+//
+//  PS/2 Keyboard Driver
+//
+
+use spin::{Mutex, Once};
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arch::io::inb;
+use arch::idt::ExceptionStackFrame;
+use arch::interrupts::set_interrupt_handler;
+use driver::pic::{PICS, PIC_1_OFFSET};
+
+/// The IRQ line the keyboard controller raises when a key is pressed or
+/// released.
+const KEYBOARD_IRQ: u8 = 1;
+
+/// The IO port we read scancodes from.
+const DATA_PORT: u16 = 0x60;
+
+/// The number of decoded characters we buffer before dropping new ones.
+const BUFFER_SIZE: usize = 128;
+
+/// The bit set in a scancode when a key is released (a "break" code), rather
+/// than pressed (a "make" code).
+const RELEASED: u8 = 0x80;
+
+/// The byte sent before the scancode of an extended key (eg. the arrow keys or
+/// right control).
+const EXTENDED_PREFIX: u8 = 0xe0;
+
+/// The scancodes of the modifier keys we track.
+const LEFT_SHIFT: u8 = 0x2a;
+const RIGHT_SHIFT: u8 = 0x36;
+const CAPS_LOCK: u8 = 0x3a;
+
+/// The ASCII character for each key in scancode set 1, indexed by the key's
+/// make code. Keys that don't produce a character map to 0.
+const SCANCODE_ASCII: [u8; 58] = [
+	0, 0x1b, b'1', b'2', b'3', b'4', b'5', b'6',
+	b'7', b'8', b'9', b'0', b'-', b'=', 0x08, b'\t',
+	b'q', b'w', b'e', b'r', b't', b'y', b'u', b'i',
+	b'o', b'p', b'[', b']', b'\n', 0, b'a', b's',
+	b'd', b'f', b'g', b'h', b'j', b'k', b'l', b';',
+	b'\'', b'`', 0, b'\\', b'z', b'x', b'c', b'v',
+	b'b', b'n', b'm', b',', b'.', b'/', 0, b'*',
+	0, b' ',
+];
+
+/// The same as `SCANCODE_ASCII`, but while shift is held down.
+const SCANCODE_ASCII_SHIFTED: [u8; 58] = [
+	0, 0x1b, b'!', b'@', b'#', b'$', b'%', b'^',
+	b'&', b'*', b'(', b')', b'_', b'+', 0x08, b'\t',
+	b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I',
+	b'O', b'P', b'{', b'}', b'\n', 0, b'A', b'S',
+	b'D', b'F', b'G', b'H', b'J', b'K', b'L', b':',
+	b'"', b'~', 0, b'|', b'Z', b'X', b'C', b'V',
+	b'B', b'N', b'M', b'<', b'>', b'?', 0, b'*',
+	0, b' ',
+];
+
+/// The static keyboard state, only ever locked by the interrupt handler.
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
+
+/// The characters typed on the keyboard that haven't been read yet.
+static INPUT: InputBuffer = InputBuffer::new();
+
+/// Tracks the state of the modifier keys, used to decode scancodes.
+struct Keyboard {
+	/// Whether either of the shift keys are held down.
+	left_shift: bool,
+	right_shift: bool,
+
+	/// Whether caps lock is on.
+	caps_lock: bool,
+
+	/// Whether the last byte we received was the extended key prefix.
+	extended: bool,
+}
+
+impl Keyboard {
+	/// Create a new keyboard with no modifiers active.
+	const fn new() -> Keyboard {
+		Keyboard {
+			left_shift: false,
+			right_shift: false,
+			caps_lock: false,
+			extended: false,
+		}
+	}
+
+	/// Updates the keyboard's state with a new scancode byte, returning the
+	/// ASCII character the byte produces, if any.
+	fn decode(&mut self, scancode: u8) -> Option<u8> {
+		// We don't support any extended keys, so ignore the byte after the
+		// extended prefix
+		if scancode == EXTENDED_PREFIX {
+			self.extended = true;
+			return None;
+		}
+		if self.extended {
+			self.extended = false;
+			return None;
+		}
+
+		// Track releasing the shift keys
+		if scancode & RELEASED != 0 {
+			match scancode & !RELEASED {
+				LEFT_SHIFT => self.left_shift = false,
+				RIGHT_SHIFT => self.right_shift = false,
+				_ => {},
+			}
+			return None;
+		}
+
+		match scancode {
+			LEFT_SHIFT => self.left_shift = true,
+			RIGHT_SHIFT => self.right_shift = true,
+			CAPS_LOCK => self.caps_lock = !self.caps_lock,
+			_ => return self.character(scancode),
+		}
+		None
+	}
+
+	/// Returns the ASCII character for a key's make code, taking into account
+	/// the modifier keys.
+	fn character(&self, scancode: u8) -> Option<u8> {
+		let index = scancode as usize;
+		if index >= SCANCODE_ASCII.len() || SCANCODE_ASCII[index] == 0 {
+			return None;
+		}
+
+		let shift = self.left_shift || self.right_shift;
+		let character = SCANCODE_ASCII[index];
+		if character >= b'a' && character <= b'z' {
+			// Caps lock inverts shift, but only for letters
+			if shift != self.caps_lock {
+				Some(character - b'a' + b'A')
+			} else {
+				Some(character)
+			}
+		} else if shift {
+			Some(SCANCODE_ASCII_SHIFTED[index])
+		} else {
+			Some(character)
+		}
+	}
+}
+
+/// A ring buffer of characters, written to by the keyboard's interrupt handler
+/// and read by the rest of the kernel.
+///
+/// We can't use a spin lock here, since if the interrupt handler fired while
+/// the kernel held the lock, it'd deadlock. Instead, only the interrupt
+/// handler moves the tail and only `read_char` moves the head, so they never
+/// write to the same slot at once.
+struct InputBuffer {
+	characters: UnsafeCell<[u8; BUFFER_SIZE]>,
+
+	/// The index of the next character to read.
+	head: AtomicUsize,
+
+	/// The index the next character will be written to.
+	tail: AtomicUsize,
+}
+
+// The head and tail indices make sure the reader and writer never access the
+// same slot at the same time
+unsafe impl Sync for InputBuffer {}
+
+impl InputBuffer {
+	/// Create a new, empty buffer.
+	const fn new() -> InputBuffer {
+		InputBuffer {
+			characters: UnsafeCell::new([0; BUFFER_SIZE]),
+			head: AtomicUsize::new(0),
+			tail: AtomicUsize::new(0),
+		}
+	}
+
+	/// Adds a character to the end of the buffer, dropping it if the buffer is
+	/// full. Must only be called from the interrupt handler.
+	fn push(&self, character: u8) {
+		let tail = self.tail.load(Ordering::Relaxed);
+		let next = (tail + 1) % BUFFER_SIZE;
+		if next == self.head.load(Ordering::Acquire) {
+			return;
+		}
+
+		unsafe { (*self.characters.get())[tail] = character };
+		self.tail.store(next, Ordering::Release);
+	}
+
+	/// Removes the character at the start of the buffer.
+	fn pop(&self) -> Option<u8> {
+		let head = self.head.load(Ordering::Relaxed);
+		if head == self.tail.load(Ordering::Acquire) {
+			return None;
+		}
+
+		let character = unsafe { (*self.characters.get())[head] };
+		self.head.store((head + 1) % BUFFER_SIZE, Ordering::Release);
+		Some(character)
+	}
+}
+
+/// Called when the keyboard controller has a scancode for us.
+extern "x86-interrupt" fn interrupt_handler(_frame: &mut ExceptionStackFrame) {
+	// We have to read the scancode, even if we don't use it, or the keyboard
+	// controller won't send any more
+	let scancode = unsafe { inb(DATA_PORT) };
+	if let Some(character) = KEYBOARD.lock().decode(scancode) {
+		INPUT.push(character);
+	}
+
+	PICS.lock().end_of_interrupt(KEYBOARD_IRQ);
+}
+
+/// Returns the next character typed on the keyboard, or `None` if there are
+/// no characters waiting to be read.
+pub fn read_char() -> Option<u8> {
+	INPUT.pop()
+}
+
+/// Returns the next character typed on the keyboard, halting the CPU until
+/// one arrives if none are waiting. Interrupts must be enabled, since the
+/// keyboard's interrupt is what wakes us up.
+pub fn wait_char() -> u8 {
+	loop {
+		if let Some(character) = read_char() {
+			return character;
+		}
+
+		// If the key is pressed between the check above and here, we still
+		// wake up on the next timer tick
+		unsafe { asm!("hlt" :::: "volatile") };
+	}
+}
+
+/// Reads a line typed on the keyboard into a buffer, returning the number of
+/// characters read once enter is pressed. The newline isn't stored.
+///
+/// Characters are echoed to the console as they're typed, and backspace
+/// erases the last character both on screen and in the buffer. Once the buffer
+/// is full, any further characters are ignored until enter is pressed.
+pub fn read_line(buffer: &mut [u8]) -> usize {
+	let mut length = 0;
+	loop {
+		match wait_char() {
+			b'\n' => {
+				print!("\n");
+				return length;
+			},
+			0x08 => {
+				if length > 0 {
+					length -= 1;
+					print!("\x08");
+				}
+			},
+			// Only store printable characters, since echoing anything else
+			// (like escape) could mess up the console
+			character @ 0x20 ... 0x7e => {
+				if length < buffer.len() {
+					buffer[length] = character;
+					length += 1;
+					print!("{}", character as char);
+				}
+			},
+			_ => {},
+		}
+	}
+}
+
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the keyboard module.
+///
+/// Installs the keyboard's interrupt handler and enables its IRQ. Must be
+/// called after the PICs are remapped.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		set_interrupt_handler(PIC_1_OFFSET + KEYBOARD_IRQ, interrupt_handler);
+		PICS.lock().unmask(KEYBOARD_IRQ);
+	});
+}
@@ -3,4 +3,14 @@
 //  Kernel Drivers
 //
 
+#[macro_use] pub mod console;
 #[macro_use] pub mod vga;
+#[macro_use] pub mod serial;
+pub mod apic;
+pub mod font;
+pub mod framebuffer;
+pub mod keyboard;
+pub mod pic;
+pub mod pit;
+pub mod rtc;
+pub mod speaker;
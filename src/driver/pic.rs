@@ -0,0 +1,186 @@
+
+//
+//  8259 Programmable Interrupt Controller Driver
+//
+
+use spin::Once;
+use arch::io::{Port, io_wait};
+use sync::IrqMutex;
+
+/// The interrupt vector that the master PIC's first IRQ is mapped to. By
+/// default, the PICs use vectors 0 to 15, which collide with the CPU's
+/// exception vectors, so we move them to the first vectors after those
+/// reserved for exceptions.
+pub const PIC_1_OFFSET: u8 = 32;
+
+/// The interrupt vector that the slave PIC's first IRQ is mapped to.
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// The master PIC's IRQ line that the slave PIC is connected to.
+const CASCADE_IRQ: u8 = 2;
+
+/// The command that starts the initialisation sequence, where we send the PIC
+/// 3 more initialisation command words (ICWs) over its data port. Bit 0 says
+/// that we'll send the 4th ICW.
+const ICW1_INIT: u8 = 0x11;
+
+/// The 4th ICW, which puts the PIC into 8086 mode.
+const ICW4_8086: u8 = 0x01;
+
+/// The command that tells a PIC we've finished handling its interrupt.
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// The static PIC pair. Interrupts are disabled while it's locked, since IRQ
+/// handlers lock it to signal the end of their interrupt.
+pub static PICS: IrqMutex<ChainedPics> =
+	IrqMutex::new(ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET));
+
+/// A single 8259 PIC, which handles 8 IRQ lines.
+struct Pic {
+	/// The interrupt vector of the PIC's first IRQ.
+	offset: u8,
+
+	command: Port<u8>,
+	data: Port<u8>,
+}
+
+impl Pic {
+	/// Sends the PIC an end of interrupt command.
+	fn end_of_interrupt(&mut self) {
+		unsafe { self.command.write(END_OF_INTERRUPT) };
+	}
+
+	/// Returns the PIC's interrupt mask, where each set bit disables an IRQ.
+	fn mask(&mut self) -> u8 {
+		unsafe { self.data.read() }
+	}
+
+	/// Sets the PIC's interrupt mask.
+	fn set_mask(&mut self, mask: u8) {
+		unsafe { self.data.write(mask) };
+	}
+}
+
+/// The two PICs on a PC: the master PIC handles IRQs 0 to 7, and the slave PIC,
+/// which is connected to the master's IRQ 2 line, handles IRQs 8 to 15.
+pub struct ChainedPics {
+	master: Pic,
+	slave: Pic,
+}
+
+impl ChainedPics {
+	/// Create a new pair of PICs, whose IRQs are mapped to the interrupt
+	/// vectors starting at the given offsets.
+	const fn new(master_offset: u8, slave_offset: u8) -> ChainedPics {
+		ChainedPics {
+			master: Pic {
+				offset: master_offset,
+				command: Port::new(0x20),
+				data: Port::new(0x21),
+			},
+			slave: Pic {
+				offset: slave_offset,
+				command: Port::new(0xa0),
+				data: Port::new(0xa1),
+			},
+		}
+	}
+
+	/// Remaps both PICs' IRQs to their interrupt vector offsets, and masks
+	/// every IRQ. Drivers should unmask the IRQs they handle.
+	pub fn init(&mut self) {
+		unsafe {
+			// Start the initialisation sequence on both PICs
+			self.master.command.write(ICW1_INIT);
+			io_wait();
+			self.slave.command.write(ICW1_INIT);
+			io_wait();
+
+			// ICW2: the interrupt vector offsets
+			self.master.data.write(self.master.offset);
+			io_wait();
+			self.slave.data.write(self.slave.offset);
+			io_wait();
+
+			// ICW3: tell the master which IRQ line has the slave (as a bit
+			// mask), and the slave its cascade identity (as a number)
+			self.master.data.write(1 << CASCADE_IRQ);
+			io_wait();
+			self.slave.data.write(CASCADE_IRQ);
+			io_wait();
+
+			// ICW4: use 8086 mode
+			self.master.data.write(ICW4_8086);
+			io_wait();
+			self.slave.data.write(ICW4_8086);
+			io_wait();
+		}
+
+		// Mask everything except the cascade line, so the slave's IRQs can get
+		// through once they're unmasked
+		self.master.set_mask(!(1 << CASCADE_IRQ));
+		self.slave.set_mask(0xff);
+	}
+
+	/// Masks every IRQ on both PICs, including the cascade line, so that
+	/// neither delivers any more interrupts (eg. once we've switched to the
+	/// APIC).
+	pub fn disable(&mut self) {
+		self.master.set_mask(0xff);
+		self.slave.set_mask(0xff);
+	}
+
+	/// Disables an IRQ line.
+	pub fn mask(&mut self, irq: u8) {
+		let (pic, line) = self.pic_for(irq);
+		let mask = pic.mask();
+		pic.set_mask(mask | (1 << line));
+	}
+
+	/// Enables an IRQ line.
+	pub fn unmask(&mut self, irq: u8) {
+		let (pic, line) = self.pic_for(irq);
+		let mask = pic.mask();
+		pic.set_mask(mask & !(1 << line));
+	}
+
+	/// Tells the PICs we've finished handling an IRQ, so they can send us
+	/// another. Must be called at the end of every IRQ handler.
+	pub fn end_of_interrupt(&mut self, irq: u8) {
+		// IRQs from the slave PIC go through the master too, so both need to
+		// be told
+		if irq >= 8 {
+			self.slave.end_of_interrupt();
+		}
+		self.master.end_of_interrupt();
+	}
+
+	/// Returns the PIC that handles an IRQ, and the IRQ's line on that PIC.
+	fn pic_for(&mut self, irq: u8) -> (&mut Pic, u8) {
+		if irq < 8 {
+			(&mut self.master, irq)
+		} else {
+			(&mut self.slave, irq - 8)
+		}
+	}
+}
+
+/// Masks every IRQ on both PICs, so they don't deliver any more interrupts.
+pub fn disable() {
+	PICS.lock().disable();
+}
+
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the PIC module.
+///
+/// Remaps the PICs' IRQs to interrupt vectors 32 to 47, and masks them all.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		PICS.lock().init();
+	});
+}
@@ -0,0 +1,122 @@
+
+//
+//  8254 Programmable Interval Timer Driver
+//
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Once;
+
+use arch::io::outb;
+use arch::idt::ExceptionStackFrame;
+use arch::interrupts::set_interrupt_handler;
+use driver::pic::{PICS, PIC_1_OFFSET};
+use driver::speaker;
+
+/// The frequency of the PIT's input clock, in Hz. Each channel divides this
+/// by a 16 bit divisor to get its output frequency.
+const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// The number of times the system timer (channel 0) fires per second.
+pub const TIMER_FREQUENCY: u32 = 1000;
+
+/// The IRQ line channel 0 is connected to.
+const TIMER_IRQ: u8 = 0;
+
+/// The data port for channel 0, which drives the system timer IRQ.
+const CHANNEL_0_PORT: u16 = 0x40;
+
+/// The data port for channel 2, whose output is connected to the PC speaker.
+const CHANNEL_2_PORT: u16 = 0x42;
+
+/// The port we send mode commands to.
+const COMMAND_PORT: u16 = 0x43;
+
+/// Bits 6 and 7 in a mode command select the channel it applies to.
+const COMMAND_CHANNEL_0: u8 = 0 << 6;
+const COMMAND_CHANNEL_2: u8 = 2 << 6;
+
+/// Bits 4 and 5 in a mode command select how the channel's divisor is
+/// written. This says we'll send the low byte followed by the high byte.
+const COMMAND_ACCESS_LOW_HIGH: u8 = 3 << 4;
+
+/// Bits 1 to 3 in a mode command select the channel's operating mode. Mode 3
+/// generates a square wave, which gives a regular IRQ on channel 0 and a tone
+/// on channel 2.
+const COMMAND_SQUARE_WAVE: u8 = 3 << 1;
+
+/// The number of times the system timer has fired since it was started.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the divisor that makes a channel output the given frequency, as
+/// close as the 16 bit divisor allows.
+fn divisor(frequency: u32) -> u16 {
+	let divisor = PIT_FREQUENCY / frequency.max(1);
+	if divisor > 0xffff {
+		0xffff
+	} else if divisor == 0 {
+		1
+	} else {
+		divisor as u16
+	}
+}
+
+/// Makes a channel output a square wave at the given frequency.
+fn set_frequency(port: u16, channel: u8, frequency: u32) {
+	let divisor = divisor(frequency);
+	unsafe {
+		outb(COMMAND_PORT, channel | COMMAND_ACCESS_LOW_HIGH | COMMAND_SQUARE_WAVE);
+		outb(port, divisor as u8);
+		outb(port, (divisor >> 8) as u8);
+	}
+}
+
+/// Sets the frequency of the tone channel 2 sends to the PC speaker.
+pub fn set_speaker_frequency(frequency: u32) {
+	set_frequency(CHANNEL_2_PORT, COMMAND_CHANNEL_2, frequency);
+}
+
+/// Returns the number of times the system timer has fired since it was
+/// started. It fires `TIMER_FREQUENCY` times per second.
+pub fn ticks() -> usize {
+	TICKS.load(Ordering::SeqCst)
+}
+
+/// Waits for the given number of timer ticks to pass, halting the CPU in
+/// between them.
+///
+/// Interrupts must be enabled, otherwise this never returns.
+pub fn wait_ticks(count: usize) {
+	let end = ticks() + count;
+	while ticks() < end {
+		unsafe { asm!("hlt" :::: "volatile") };
+	}
+}
+
+/// The system timer's interrupt handler.
+extern "x86-interrupt" fn interrupt_handler(_frame: &mut ExceptionStackFrame) {
+	let ticks = TICKS.fetch_add(1, Ordering::SeqCst) + 1;
+
+	// Give the speaker a chance to stop any beep that's finished
+	speaker::tick(ticks);
+
+	PICS.lock().end_of_interrupt(TIMER_IRQ);
+}
+
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the PIT module.
+///
+/// Starts the system timer at `TIMER_FREQUENCY`, installs its interrupt
+/// handler, and enables its IRQ. Must be called after the PIC is initialised.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		set_frequency(CHANNEL_0_PORT, COMMAND_CHANNEL_0, TIMER_FREQUENCY);
+		set_interrupt_handler(PIC_1_OFFSET + TIMER_IRQ, interrupt_handler);
+		PICS.lock().unmask(TIMER_IRQ);
+	});
+}
@@ -0,0 +1,167 @@
+
+//
+//  Real Time Clock Driver
+//
+
+use core::fmt;
+
+use spin::Mutex;
+use arch::io::Port;
+
+/// The IO port used to select a CMOS register.
+const CMOS_ADDRESS: u16 = 0x70;
+
+/// The IO port used to read the CMOS register selected through
+/// `CMOS_ADDRESS`.
+const CMOS_DATA: u16 = 0x71;
+
+/// The CMOS registers holding each part of the current date and time.
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+
+/// The CMOS status registers A and B.
+const REGISTER_STATUS_A: u8 = 0x0a;
+const REGISTER_STATUS_B: u8 = 0x0b;
+
+/// The bit in status register A that's set while the RTC is updating its
+/// registers. Reading them during an update can give a mix of the old and new
+/// time.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+/// The bit in status register B that's set if the hours register uses the 24
+/// hour format, rather than 12 hour.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+
+/// The bit in status register B that's set if the registers hold binary values,
+/// rather than binary coded decimal (BCD).
+const STATUS_B_BINARY: u8 = 1 << 2;
+
+/// The bit in the hours register that's set for PM times, when using the 12
+/// hour format.
+const HOURS_PM: u8 = 1 << 7;
+
+/// The CMOS has no reliable century register, so we assume every year is in
+/// this century.
+const CENTURY: u16 = 2000;
+
+/// The static CMOS, which holds the real time clock's registers.
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos::new());
+
+/// A date and time read from the real time clock. The RTC doesn't store a time
+/// zone, but is normally set to UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+	pub year: u16,
+	pub month: u8,
+	pub day: u8,
+	pub hour: u8,
+	pub minute: u8,
+	pub second: u8,
+}
+
+impl fmt::Display for DateTime {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}", self.year, self.month,
+			self.day, self.hour, self.minute, self.second)
+	}
+}
+
+/// The CMOS RAM, accessed by writing a register number to the address port and
+/// then reading its value from the data port.
+struct Cmos {
+	address: Port<u8>,
+	data: Port<u8>,
+}
+
+impl Cmos {
+	/// Creates a new CMOS using the standard IO ports.
+	const fn new() -> Cmos {
+		Cmos {
+			address: Port::new(CMOS_ADDRESS),
+			data: Port::new(CMOS_DATA),
+		}
+	}
+
+	/// Reads a CMOS register.
+	fn read_register(&mut self, register: u8) -> u8 {
+		unsafe {
+			self.address.write(register);
+			self.data.read()
+		}
+	}
+
+	/// Returns true if the RTC is currently updating its registers.
+	fn update_in_progress(&mut self) -> bool {
+		self.read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+	}
+
+	/// Reads the raw time registers, after waiting for any update in progress
+	/// to finish. The values are left in whatever format the RTC uses.
+	fn read_raw(&mut self) -> DateTime {
+		while self.update_in_progress() {}
+
+		DateTime {
+			year: self.read_register(REGISTER_YEAR) as u16,
+			month: self.read_register(REGISTER_MONTH),
+			day: self.read_register(REGISTER_DAY),
+			hour: self.read_register(REGISTER_HOURS),
+			minute: self.read_register(REGISTER_MINUTES),
+			second: self.read_register(REGISTER_SECONDS),
+		}
+	}
+
+	/// Reads the current date and time.
+	fn now(&mut self) -> DateTime {
+		// An update can still start between checking the update in progress
+		// flag and reading the registers, so keep reading until we get the
+		// same value twice in a row
+		let mut time = self.read_raw();
+		loop {
+			let next = self.read_raw();
+			if next == time {
+				break;
+			}
+			time = next;
+		}
+
+		let status = self.read_register(REGISTER_STATUS_B);
+
+		// Convert from BCD if needed, keeping the PM bit in the hours register
+		// intact
+		if status & STATUS_B_BINARY == 0 {
+			time.second = bcd_to_binary(time.second);
+			time.minute = bcd_to_binary(time.minute);
+			time.hour = bcd_to_binary(time.hour & !HOURS_PM) | (time.hour & HOURS_PM);
+			time.day = bcd_to_binary(time.day);
+			time.month = bcd_to_binary(time.month);
+			time.year = bcd_to_binary(time.year as u8) as u16;
+		}
+
+		// Convert from the 12 hour format, where midnight and noon are both 12
+		if status & STATUS_B_24_HOUR == 0 {
+			let pm = time.hour & HOURS_PM != 0;
+			time.hour = (time.hour & !HOURS_PM) % 12;
+			if pm {
+				time.hour += 12;
+			}
+		}
+
+		time.year += CENTURY;
+		time
+	}
+}
+
+/// Converts a binary coded decimal (BCD) byte, which stores a decimal digit in
+/// each nibble, to binary.
+fn bcd_to_binary(value: u8) -> u8 {
+	(value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Returns the current date and time from the real time clock.
+pub fn now() -> DateTime {
+	CMOS.lock().now()
+}
@@ -0,0 +1,176 @@
+
+//
+//  Serial Port Driver
+//
+
+use spin::Once;
+use arch::io::Port;
+use sync::IrqMutex;
+use driver::console::Console;
+use driver::vga::Color;
+
+use core::fmt;
+
+/// The base IO port for the first serial port (COM1).
+const COM1_BASE: u16 = 0x3f8;
+
+/// The bit in the line status register that's set when the transmit buffer
+/// is empty, meaning we can send another byte.
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// The ANSI SGR parameter that sets the foreground to each VGA color, indexed
+/// by the color's number. Adding 10 gives the parameter for the background.
+const ANSI_FOREGROUND: [u8; 16] = [
+	30, 34, 32, 36, 31, 35, 33, 37,
+	90, 94, 92, 96, 91, 95, 93, 97,
+];
+
+/// The static serial port used to output debug messages. Interrupts are
+/// disabled while it's locked, in case an interrupt handler tries to print
+/// something.
+pub static COM1: IrqMutex<SerialPort> = IrqMutex::new(SerialPort::new(COM1_BASE));
+
+/// A 16550 UART serial port, which writes bytes over a serial line.
+///
+/// When the Divisor Latch Access Bit (DLAB) in the line control register is
+/// set, the data and interrupt enable ports are instead used to set the low
+/// and high bytes of the baud rate divisor.
+pub struct SerialPort {
+	data: Port<u8>,
+	interrupt_enable: Port<u8>,
+	fifo_control: Port<u8>,
+	line_control: Port<u8>,
+	modem_control: Port<u8>,
+	line_status: Port<u8>,
+
+	/// The colors last set using ANSI escape sequences, so they can be
+	/// restored by the console.
+	foreground: Color,
+	background: Color,
+}
+
+impl SerialPort {
+	/// Create a new serial port whose registers start at the given IO port.
+	const fn new(base: u16) -> SerialPort {
+		SerialPort {
+			data: Port::new(base),
+			interrupt_enable: Port::new(base + 1),
+			fifo_control: Port::new(base + 2),
+			line_control: Port::new(base + 3),
+			modem_control: Port::new(base + 4),
+			line_status: Port::new(base + 5),
+			foreground: Color::White,
+			background: Color::Black,
+		}
+	}
+
+	/// Configures the UART to send data at 38400 baud, with 8 data bits, no
+	/// parity bit, and 1 stop bit (8N1).
+	pub fn init(&mut self) {
+		unsafe {
+			// Disable all interrupts, since we poll the line status instead
+			self.interrupt_enable.write(0x00);
+
+			// Set the DLAB so we can set the baud rate divisor. The UART's
+			// clock runs at 115200 Hz, so a divisor of 3 gives 38400 baud
+			self.line_control.write(0x80);
+			self.data.write(0x03);
+			self.interrupt_enable.write(0x00);
+
+			// Clear the DLAB and use 8 data bits, no parity, and 1 stop bit
+			self.line_control.write(0x03);
+
+			// Enable and clear the FIFO buffers, with a 14 byte threshold
+			self.fifo_control.write(0xc7);
+
+			// Set the data terminal ready (DTR) and request to send (RTS) bits
+			self.modem_control.write(0x03);
+		}
+	}
+
+	/// Returns true if the UART is ready for us to send another byte.
+	fn is_transmit_empty(&mut self) -> bool {
+		unsafe { self.line_status.read() & TRANSMIT_EMPTY != 0 }
+	}
+
+	/// Sends a single byte over the serial line, waiting until the UART is
+	/// ready to accept it.
+	fn write_byte(&mut self, byte: u8) {
+		while !self.is_transmit_empty() {}
+		unsafe { self.data.write(byte) };
+	}
+}
+
+impl fmt::Write for SerialPort {
+	fn write_str(&mut self, string: &str) -> fmt::Result {
+		for byte in string.bytes() {
+			self.write_byte(byte);
+		}
+		Ok(())
+	}
+}
+
+/// The serial port is treated as a console on an ANSI terminal, so colors and
+/// cursor movement are sent as escape sequences.
+impl Console for SerialPort {
+	fn set_color(&mut self, foreground: Color, background: Color) {
+		use core::fmt::Write;
+		self.foreground = foreground;
+		self.background = background;
+		write!(self, "\x1b[{};{}m", ANSI_FOREGROUND[foreground as usize],
+			ANSI_FOREGROUND[background as usize] + 10).unwrap();
+	}
+
+	fn color(&self) -> (Color, Color) {
+		(self.foreground, self.background)
+	}
+
+	fn clear(&mut self) {
+		use core::fmt::Write;
+		self.write_str("\x1b[2J\x1b[H").unwrap();
+	}
+
+	fn set_cursor(&mut self, x: usize, y: usize) {
+		use core::fmt::Write;
+
+		// ANSI positions start at 1 rather than 0
+		write!(self, "\x1b[{};{}H", y + 1, x + 1).unwrap();
+	}
+}
+
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the serial module.
+///
+/// Configures the COM1 serial port so that we can write to it.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		COM1.lock().init();
+	});
+}
+
+
+/// A macro to print a format string and arguments to the serial port.
+macro_rules! serial_print {
+    ($($arg:tt)*) => ({
+        $crate::driver::serial::print(format_args!($($arg)*));
+    });
+}
+
+/// Prints a string to the serial port, appending a newline after it.
+macro_rules! serial_println {
+    ($fmt:expr) => (serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Prints a series of format arguments to the serial port.
+pub fn print(args: fmt::Arguments) {
+	// Like the VGA driver, the lock is taken here rather than in the macro to
+	// avoid deadlocking on nested calls
+    use core::fmt::Write;
+    COM1.lock().write_fmt(args).unwrap();
+}
@@ -0,0 +1,77 @@
+
+//
+//  PC Speaker Driver
+//
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use arch::io::{inb, outb};
+use arch::interrupts::without_interrupts;
+use driver::pit;
+
+/// The IO port controlling the PC speaker (port B of the keyboard controller).
+const SPEAKER_PORT: u16 = 0x61;
+
+/// Bit 0 in the speaker port gates PIT channel 2, and bit 1 connects the
+/// channel's output to the speaker. Both need to be set to make a sound.
+const SPEAKER_ENABLE: u8 = 0b11;
+
+/// The frequency of the bell tone, in Hz.
+const BELL_FREQUENCY: u32 = 880;
+
+/// How long the bell tone plays for, in milliseconds.
+const BELL_DURATION: u32 = 100;
+
+/// If set, beeps are silently ignored.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// The timer tick at which the current beep should stop, or 0 if the speaker
+/// is silent.
+static STOP_AT: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets whether beeps are silenced (eg. so that test runs aren't noisy).
+pub fn set_quiet(quiet: bool) {
+	QUIET.store(quiet, Ordering::SeqCst);
+}
+
+/// Plays a tone at the given frequency (in Hz) for the given number of
+/// milliseconds. Returns immediately; the system timer stops the tone once
+/// it's finished.
+pub fn beep(frequency: u32, duration: u32) {
+	if QUIET.load(Ordering::SeqCst) {
+		return;
+	}
+
+	// Play for at least one tick, so the tone doesn't get stopped straight
+	// away (and so `STOP_AT` never ends up as 0)
+	let ticks = (duration * pit::TIMER_FREQUENCY / 1000).max(1) as usize;
+
+	// Disable interrupts so the timer can't stop the speaker part way through
+	// us starting it
+	without_interrupts(|| {
+		pit::set_speaker_frequency(frequency);
+		unsafe {
+			let value = inb(SPEAKER_PORT);
+			outb(SPEAKER_PORT, value | SPEAKER_ENABLE);
+		}
+		STOP_AT.store(pit::ticks() + ticks, Ordering::SeqCst);
+	});
+}
+
+/// Plays a short bell tone, used for the BEL (0x07) character.
+pub fn bell() {
+	beep(BELL_FREQUENCY, BELL_DURATION);
+}
+
+/// Called by the system timer's interrupt handler on every tick, to stop the
+/// current beep once it's played for long enough.
+pub fn tick(ticks: usize) {
+	let stop_at = STOP_AT.load(Ordering::SeqCst);
+	if stop_at != 0 && ticks >= stop_at {
+		STOP_AT.store(0, Ordering::SeqCst);
+		unsafe {
+			let value = inb(SPEAKER_PORT);
+			outb(SPEAKER_PORT, value & !SPEAKER_ENABLE);
+		}
+	}
+}
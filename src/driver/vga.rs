@@ -4,19 +4,127 @@
 //
 
 use volatile::Volatile;
-use spin::Mutex;
+use spin::Once;
+use arch::io::{inb, outb};
+use sync::IrqMutex;
+use driver::console::Console;
+use driver::speaker;
 
 use core::fmt;
 use core::ptr::Unique;
 
-/// The width of the terminal window, in cells.
-const TERM_WIDTH: usize = 80;
+/// The default width of the terminal window, in cells, for the standard 80x25
+/// text mode.
+const DEFAULT_WIDTH: usize = 80;
 
-/// The height of the terminal window, in cells.
-const TERM_HEIGHT: usize = 25;
+/// The default height of the terminal window, in cells.
+const DEFAULT_HEIGHT: usize = 25;
 
-/// The static Writer used to output characters to the terminal.
-pub static WRITER: Mutex<Writer> = Mutex::new(Writer::vga());
+/// The largest width the terminal window can be set to, in cells.
+const MAX_WIDTH: usize = 80;
+
+/// The largest height the terminal window can be set to, in cells, which is
+/// enough for the 80x50 text mode.
+const MAX_HEIGHT: usize = 50;
+
+/// The physical address of the VGA text buffer.
+const VGA_BUFFER: usize = 0xb8000;
+
+/// The IO port used to select which CRT controller register to access.
+const CRTC_INDEX: u16 = 0x3d4;
+
+/// The IO port used to read or write the selected CRT controller register.
+const CRTC_DATA: u16 = 0x3d5;
+
+/// The CRT controller register holding the cursor's start scanline, whose
+/// 5th bit disables the cursor when set.
+const CRTC_CURSOR_START: u8 = 0x0a;
+
+/// The CRT controller registers holding the high and low bytes of the
+/// cursor's location, as an offset in cells from the top left of the screen.
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+
+/// The IO port used both to select an attribute controller register and to
+/// write to it. Which one a write does alternates, starting with the index
+/// after `INPUT_STATUS` is read.
+const ATTRIBUTE_INDEX: u16 = 0x3c0;
+
+/// The IO port used to read the selected attribute controller register.
+const ATTRIBUTE_READ: u16 = 0x3c1;
+
+/// Reading this port (input status register 1) resets the attribute
+/// controller, so that the next write to `ATTRIBUTE_INDEX` selects a register.
+const INPUT_STATUS: u16 = 0x3da;
+
+/// Set in the attribute controller's index alongside the register number, to
+/// keep the screen on while we change the register. Without it, the screen
+/// goes blank.
+const ATTRIBUTE_PALETTE_SOURCE: u8 = 1 << 5;
+
+/// The attribute controller register controlling the text mode's behaviour.
+const ATTRIBUTE_MODE_CONTROL: u8 = 0x10;
+
+/// Bit 3 in the attribute mode control register is the blink enable bit. When
+/// it's set, the highest bit of a cell's background color makes the cell's
+/// text blink, and only the 8 dark colors can be used as the background. When
+/// it's clear, the bit selects the bright version of the background color
+/// instead, so all 16 colors can be used.
+const MODE_CONTROL_BLINK: u8 = 1 << 3;
+
+/// The number of rows at the top of the terminal reserved for the status bar,
+/// when it's enabled.
+const STATUS_ROWS: usize = 1;
+
+/// The number of lines that have scrolled off the top of the terminal that we
+/// keep, so that they can be viewed again.
+const SCROLLBACK_LINES: usize = 200;
+
+/// The maximum number of numeric parameters kept from an ANSI escape
+/// sequence. Any extra parameters are ignored.
+const MAX_ESCAPE_PARAMS: usize = 4;
+
+/// Every VGA color, indexed by its number.
+const COLORS: [Color; 16] = [
+	Color::Black, Color::Blue, Color::Green, Color::Cyan,
+	Color::Red, Color::Magenta, Color::Brown, Color::LightGray,
+	Color::DarkGray, Color::LightBlue, Color::LightGreen, Color::LightCyan,
+	Color::LightRed, Color::Pink, Color::Yellow, Color::White,
+];
+
+/// The VGA colors corresponding to each of the 8 standard ANSI colors, in the
+/// order ANSI numbers them (black, red, green, yellow, blue, magenta, cyan,
+/// white).
+const ANSI_COLORS: [Color; 8] = [
+	Color::Black, Color::Red, Color::Green, Color::Brown,
+	Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray,
+];
+
+/// The VGA colors corresponding to each of the 8 bright ANSI colors.
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+	Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+	Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+/// The single line box drawing characters in code page 437, the VGA text
+/// mode's character set.
+const BOX_HORIZONTAL: u8 = 0xc4;
+const BOX_VERTICAL: u8 = 0xb3;
+const BOX_TOP_LEFT: u8 = 0xda;
+const BOX_TOP_RIGHT: u8 = 0xbf;
+const BOX_BOTTOM_LEFT: u8 = 0xc0;
+const BOX_BOTTOM_RIGHT: u8 = 0xd9;
+
+/// The default foreground color.
+const DEFAULT_FOREGROUND: Color = Color::White;
+
+/// The default background color.
+const DEFAULT_BACKGROUND: Color = Color::Black;
+
+/// The static Writer used to output characters to the terminal. Interrupts are
+/// disabled while it's locked, in case an interrupt handler tries to print
+/// something.
+pub static WRITER: IrqMutex<Writer> = IrqMutex::new(Writer::vga());
 
 /// All possible foreground and background colors we can use.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -50,6 +158,26 @@ impl CombinedColor {
 	const fn new(foreground: Color, background: Color) -> CombinedColor {
 		CombinedColor((background as u8) << 4 | (foreground as u8))
 	}
+
+	/// Returns a copy of this color with its foreground replaced.
+	fn with_foreground(self, foreground: Color) -> CombinedColor {
+		CombinedColor((self.0 & 0xf0) | (foreground as u8))
+	}
+
+	/// Returns a copy of this color with its background replaced.
+	fn with_background(self, background: Color) -> CombinedColor {
+		CombinedColor((background as u8) << 4 | (self.0 & 0x0f))
+	}
+
+	/// Returns the foreground half of this color.
+	fn foreground(self) -> Color {
+		COLORS[(self.0 & 0x0f) as usize]
+	}
+
+	/// Returns the background half of this color.
+	fn background(self) -> Color {
+		COLORS[(self.0 >> 4) as usize]
+	}
 }
 
 /// Stores a cell's foreground color, background color, and ASCII character.
@@ -60,9 +188,19 @@ struct Cell {
 	color: CombinedColor,
 }
 
+/// A cell containing a space, used to initialise buffers.
+const BLANK_CELL: Cell = Cell {
+	character: b' ',
+	color: CombinedColor::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+};
+
 /// Stores all cells on a terminal window.
+///
+/// The cells are stored row by row, with no gaps between rows, so the layout
+/// depends on the terminal's width. Only the first `width * height` cells are
+/// used.
 struct Buffer {
-	cells: [[Volatile<Cell>; TERM_WIDTH]; TERM_HEIGHT],
+	cells: [Volatile<Cell>; MAX_WIDTH * MAX_HEIGHT],
 }
 
 /// Stores all information associated with the cursor while writing to the
@@ -74,10 +212,119 @@ struct Cursor {
 	color: CombinedColor,
 }
 
+/// Stores lines that have scrolled off the top of the terminal, and what part
+/// of them is currently being viewed.
+///
+/// Since we don't have a heap, the history is a fixed size ring buffer stored
+/// inside the static `WRITER`.
+struct Scrollback {
+	/// A ring buffer of lines, in the order they scrolled off the screen.
+	lines: [[Cell; MAX_WIDTH]; SCROLLBACK_LINES],
+
+	/// The index in `lines` of the oldest line.
+	start: usize,
+
+	/// The number of lines stored in `lines`.
+	count: usize,
+
+	/// How many lines above the bottom of the terminal we're viewing. This is 0
+	/// when the terminal is showing its live contents.
+	offset: usize,
+
+	/// A copy of the terminal's live contents, taken when we start viewing
+	/// the history, so that they can be restored afterwards.
+	screen: [[Cell; MAX_WIDTH]; MAX_HEIGHT],
+}
+
+impl Scrollback {
+	/// Create a new, empty scrollback history.
+	const fn new() -> Scrollback {
+		Scrollback {
+			lines: [[BLANK_CELL; MAX_WIDTH]; SCROLLBACK_LINES],
+			start: 0,
+			count: 0,
+			offset: 0,
+			screen: [[BLANK_CELL; MAX_WIDTH]; MAX_HEIGHT],
+		}
+	}
+
+	/// Adds a line to the end of the history, replacing the oldest line if
+	/// the history is full.
+	fn push(&mut self, line: [Cell; MAX_WIDTH]) {
+		let index = (self.start + self.count) % SCROLLBACK_LINES;
+		self.lines[index] = line;
+		if self.count < SCROLLBACK_LINES {
+			self.count += 1;
+		} else {
+			self.start = (self.start + 1) % SCROLLBACK_LINES;
+		}
+	}
+
+	/// Returns the line at the given index into the history, where 0 is the
+	/// oldest line.
+	fn line(&self, index: usize) -> [Cell; MAX_WIDTH] {
+		self.lines[(self.start + index) % SCROLLBACK_LINES]
+	}
+}
+
+/// Each state the writer can be in while parsing an ANSI escape sequence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EscapeState {
+	/// Not in an escape sequence, so bytes are printed as normal.
+	Normal,
+
+	/// We've seen an ESC byte, and expect a `[` to start a control sequence.
+	Escape,
+
+	/// We're inside a control sequence (after `ESC [`), collecting numeric
+	/// parameters until we reach the final byte that says what to do.
+	Control,
+}
+
+/// Stores a partially parsed ANSI escape sequence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EscapeSequence {
+	state: EscapeState,
+
+	/// The numeric parameters collected so far, separated by `;` in the
+	/// sequence.
+	params: [usize; MAX_ESCAPE_PARAMS],
+
+	/// The number of parameters we've started collecting.
+	count: usize,
+}
+
 /// Writes text to the screen in a terminal-style fashion, moving the contents
 /// of the screen up when we reach the end of the terminal.
 pub struct Writer {
+	/// The terminal's size, in cells.
+	width: usize,
+	height: usize,
+
 	cursor: Cursor,
+	escape: EscapeSequence,
+	scrollback: Scrollback,
+
+	/// If set, writes go to `back_buffer` rather than the text buffer, and are
+	/// only copied to the text buffer when `flush` is called.
+	double_buffered: bool,
+
+	/// An in-memory copy of the screen, which is written to instead of the
+	/// text buffer when double buffering is enabled.
+	back_buffer: [[Cell; MAX_WIDTH]; MAX_HEIGHT],
+
+	/// Which rows of `back_buffer` have changed since the last flush.
+	dirty_rows: [bool; MAX_HEIGHT],
+
+	/// If set, the top `STATUS_ROWS` rows of the terminal are reserved for a
+	/// status bar, which text written to the terminal never scrolls or
+	/// overwrites.
+	status_bar: bool,
+
+	/// Whether the VGA's blink enable bit is set, which decides whether the
+	/// bright background colors make text blink instead. The BIOS enables it
+	/// by default.
+	blink: bool,
 
 	/// A `Unique` is a wrapper around a raw mutable pointer which indicates
 	/// that we own the pointer.
@@ -87,16 +334,53 @@ pub struct Writer {
 impl Writer {
 	/// Create a new writer for the kernel's VGA buffer.
 	const fn vga() -> Writer {
+		unsafe { Writer::with_buffer(VGA_BUFFER) }
+	}
+
+	/// Create a new writer for the text buffer at the given address.
+	///
+	/// This is unsafe because the address must point to a valid text buffer
+	/// big enough for `MAX_WIDTH` by `MAX_HEIGHT` cells, which the writer has
+	/// exclusive access to.
+	///
+	/// Since a writer is too large to create on the kernel's stack, this is
+	/// only intended for creating statics. Use `set_buffer` to move an existing
+	/// writer to a new buffer.
+	pub const unsafe fn with_buffer(address: usize) -> Writer {
 		Writer {
+			width: DEFAULT_WIDTH,
+			height: DEFAULT_HEIGHT,
 			cursor: Cursor {
 				x: 0,
 				y: 0,
-				color: CombinedColor::new(Color::White, Color::Black),
+				color: CombinedColor::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
 			},
-			buffer: unsafe { Unique::new(0xb8000 as *mut _) },
+			escape: EscapeSequence {
+				state: EscapeState::Normal,
+				params: [0; MAX_ESCAPE_PARAMS],
+				count: 0,
+			},
+			scrollback: Scrollback::new(),
+			double_buffered: false,
+			back_buffer: [[BLANK_CELL; MAX_WIDTH]; MAX_HEIGHT],
+			dirty_rows: [false; MAX_HEIGHT],
+			status_bar: false,
+			blink: true,
+			buffer: Unique::new(address as *mut _),
 		}
 	}
 
+	/// Moves the writer to the text buffer at the given address, eg. after
+	/// the VGA buffer is remapped to a different virtual address. The contents
+	/// of the new buffer aren't changed.
+	///
+	/// This is unsafe because the address must point to a valid text buffer
+	/// big enough for `MAX_WIDTH` by `MAX_HEIGHT` cells, which the writer has
+	/// exclusive access to.
+	pub unsafe fn set_buffer(&mut self, address: usize) {
+		self.buffer = Unique::new(address as *mut _);
+	}
+
 	/// Returns a safe, mutable pointer to the writer's buffer.
 	fn buffer(&mut self) -> &mut Buffer {
 		// It's safe to use the unsafe call here because it's an invariant of
@@ -104,59 +388,625 @@ impl Writer {
 		unsafe { self.buffer.get_mut() }
 	}
 
+	/// Sets the size of the terminal, eg. after the VGA hardware is switched
+	/// to a different text mode. The size is limited to `MAX_WIDTH` by
+	/// `MAX_HEIGHT` cells.
+	///
+	/// The screen is cleared and the cursor is moved to the first row, since
+	/// the text buffer's layout changes with the terminal's width.
+	pub fn set_dimensions(&mut self, width: usize, height: usize) {
+		self.snap_to_bottom();
+		self.width = width.max(1).min(MAX_WIDTH);
+		self.height = height.max(STATUS_ROWS + 1).min(MAX_HEIGHT);
+
+		if self.status_bar {
+			for y in 0 .. STATUS_ROWS {
+				self.clear_row(y);
+			}
+		}
+		self.clear_screen();
+		self.set_cursor(0, 0);
+	}
+
+	/// Returns the size of the terminal as a `(width, height)` pair, in cells.
+	pub fn dimensions(&self) -> (usize, usize) {
+		(self.width, self.height)
+	}
+
+	/// Returns the cell in the text buffer at the given position.
+	fn hardware_cell(&mut self, x: usize, y: usize) -> &mut Volatile<Cell> {
+		let index = y * self.width + x;
+		&mut self.buffer().cells[index]
+	}
+
+	/// Returns the cell at the given position on the screen.
+	fn read_cell(&mut self, x: usize, y: usize) -> Cell {
+		if self.double_buffered {
+			self.back_buffer[y][x]
+		} else {
+			self.hardware_cell(x, y).read()
+		}
+	}
+
+	/// Sets the cell at the given position on the screen. When double
+	/// buffering, the change isn't visible until the next `flush`.
+	fn write_cell(&mut self, x: usize, y: usize, cell: Cell) {
+		if self.double_buffered {
+			self.back_buffer[y][x] = cell;
+			self.dirty_rows[y] = true;
+		} else {
+			// Use a volatile write so that the compiler doesn't optimise out
+			// our write to the buffer
+			self.hardware_cell(x, y).write(cell);
+		}
+	}
+
+	/// Enables or disables double buffering.
+	///
+	/// While double buffering, all writes go to an in-memory copy of the
+	/// screen, and are copied to the text buffer in one go by `flush`. This
+	/// avoids flicker and is faster when writing a lot of text, but nothing
+	/// written is visible until the next flush. It's disabled by default, so
+	/// that output (eg. from a panic) appears immediately.
+	pub fn set_double_buffered(&mut self, enabled: bool) {
+		if enabled == self.double_buffered {
+			return;
+		}
+
+		if enabled {
+			// Start with a copy of what's currently on the screen
+			for y in 0 .. self.height {
+				for x in 0 .. self.width {
+					let cell = self.hardware_cell(x, y).read();
+					self.back_buffer[y][x] = cell;
+				}
+			}
+			self.double_buffered = true;
+		} else {
+			// Make sure nothing written so far is lost
+			self.flush();
+			self.double_buffered = false;
+		}
+	}
+
+	/// Copies every row of the back buffer that's changed since the last flush
+	/// to the text buffer. Does nothing if double buffering is disabled.
+	pub fn flush(&mut self) {
+		for y in 0 .. self.height {
+			if !self.dirty_rows[y] {
+				continue;
+			}
+
+			for x in 0 .. self.width {
+				let cell = self.back_buffer[y][x];
+				self.hardware_cell(x, y).write(cell);
+			}
+			self.dirty_rows[y] = false;
+		}
+	}
+
+	/// Returns the first row that text is written to, which is below the
+	/// status bar if it's enabled.
+	fn first_row(&self) -> usize {
+		if self.status_bar {
+			STATUS_ROWS
+		} else {
+			0
+		}
+	}
+
+	/// Enables or disables the status bar at the top of the terminal.
+	///
+	/// When enabled, the top row is cleared and reserved for `set_status`.
+	/// Text written to the terminal scrolls beneath it, and the cursor can't
+	/// be moved onto it. It's best enabled before anything is written, since
+	/// the top row's contents are replaced.
+	pub fn set_status_bar(&mut self, enabled: bool) {
+		self.status_bar = enabled;
+		if enabled {
+			for y in 0 .. STATUS_ROWS {
+				self.clear_row(y);
+			}
+
+			// Move the cursor off the status bar. Only its row changes, since
+			// after a full line `x` is one past the last column (so the next
+			// character wraps), which `set_cursor` would clamp
+			if self.cursor.y < STATUS_ROWS {
+				self.cursor.y = STATUS_ROWS;
+				self.update_hardware_cursor();
+			}
+		}
+	}
+
+	/// Replaces the contents of the status bar with the given text and colors.
+	/// Text longer than the terminal's width is cut off. Does nothing if the
+	/// status bar isn't enabled.
+	pub fn set_status(&mut self, text: &str, foreground: Color,
+			background: Color) {
+		if !self.status_bar {
+			return;
+		}
+
+		self.snap_to_bottom();
+		let color = CombinedColor::new(foreground, background);
+		let mut bytes = text.bytes();
+		for x in 0 .. self.width {
+			self.write_cell(x, 0, Cell {
+				character: bytes.next().unwrap_or(b' '),
+				color: color,
+			});
+		}
+	}
+
 	/// Clears a single row, replacing each character in the row with spaces,
 	/// using the cursor's current foreground and background colors.
 	pub fn clear_row(&mut self, y: usize) {
+		self.snap_to_bottom();
+
 		// Iterate over each cell in the row
-		for x in 0 .. TERM_WIDTH {
+		for x in 0 .. self.width {
 			// Set the cell at (x, y)
 			let color = self.cursor.color;
-			self.buffer().cells[y][x].write(Cell {
+			self.write_cell(x, y, Cell {
 				character: b' ',
 				color: color,
 			});
 		}
 	}
 
-	/// Clear the entire terminal to the cursor's current background color.
+	/// Clear the entire terminal (except the status bar) to the cursor's current
+	/// background color.
 	pub fn clear_screen(&mut self) {
-		// Iterate over each row
-		for y in 0 .. TERM_HEIGHT {
+		self.snap_to_bottom();
+
+		// Iterate over each row, leaving the status bar alone
+		for y in self.first_row() .. self.height {
 			// Clear this row
 			self.clear_row(y);
 		}
 	}
 
-	/// Sets the cursor's position.
+	/// Puts the terminal back into a known state: white text on black, an
+	/// empty screen, and the cursor in the top left. Any half written escape
+	/// sequence is abandoned. Unlike `init`, this can be called any number of
+	/// times.
+	pub fn reset(&mut self) {
+		self.escape.state = EscapeState::Normal;
+		self.set_color(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+		self.clear_screen();
+		self.set_cursor(0, 0);
+	}
+
+	/// Clears the cells from the cursor to the end of its line, using the
+	/// cursor's current colors. The cursor doesn't move.
+	pub fn clear_to_eol(&mut self) {
+		self.snap_to_bottom();
+
+		let cursor = self.cursor;
+		for x in cursor.x .. self.width {
+			self.write_cell(x, cursor.y, Cell {
+				character: b' ',
+				color: cursor.color,
+			});
+		}
+	}
+
+	/// Clears the cells from the cursor to the end of the screen, using the
+	/// cursor's current colors. The cursor doesn't move.
+	pub fn clear_to_eos(&mut self) {
+		// Clear the rest of the cursor's line, then every line below it
+		self.clear_to_eol();
+		for y in (self.cursor.y + 1) .. self.height {
+			self.clear_row(y);
+		}
+	}
+
+	/// Sets the cell at the given position to a character and color, without
+	/// moving the cursor. Positions outside the terminal are ignored.
+	pub fn put_char(&mut self, x: usize, y: usize, character: u8,
+			foreground: Color, background: Color) {
+		if x >= self.width || y >= self.height {
+			return;
+		}
+
+		self.snap_to_bottom();
+		self.write_cell(x, y, Cell {
+			character: character,
+			color: CombinedColor::new(foreground, background),
+		});
+	}
+
+	/// Fills a rectangle of cells with a character and color, without moving
+	/// the cursor. Any part of the rectangle outside the terminal is clipped.
+	pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize,
+			character: u8, foreground: Color, background: Color) {
+		let right = x.saturating_add(width).min(self.width);
+		let bottom = y.saturating_add(height).min(self.height);
+		for cell_y in y .. bottom {
+			for cell_x in x .. right {
+				self.put_char(cell_x, cell_y, character, foreground, background);
+			}
+		}
+	}
+
+	/// Draws the outline of a box using the single line drawing characters
+	/// from code page 437, without moving the cursor or changing the cells
+	/// inside the box. Any part of the box outside the terminal is clipped.
+	///
+	/// The box's outline includes the cells at `x` and `x + width - 1`, and
+	/// `y` and `y + height - 1`.
+	pub fn draw_box(&mut self, x: usize, y: usize, width: usize, height: usize,
+			foreground: Color, background: Color) {
+		if width == 0 || height == 0 {
+			return;
+		}
+
+		let right = x.saturating_add(width - 1);
+		let bottom = y.saturating_add(height - 1);
+
+		// The horizontal edges
+		for cell_x in x.saturating_add(1) .. right {
+			self.put_char(cell_x, y, BOX_HORIZONTAL, foreground, background);
+			self.put_char(cell_x, bottom, BOX_HORIZONTAL, foreground, background);
+		}
+
+		// The vertical edges
+		for cell_y in y.saturating_add(1) .. bottom {
+			self.put_char(x, cell_y, BOX_VERTICAL, foreground, background);
+			self.put_char(right, cell_y, BOX_VERTICAL, foreground, background);
+		}
+
+		// The corners go last, so they overwrite the edges of boxes that are
+		// only 1 cell wide or high
+		self.put_char(x, y, BOX_TOP_LEFT, foreground, background);
+		self.put_char(right, y, BOX_TOP_RIGHT, foreground, background);
+		self.put_char(x, bottom, BOX_BOTTOM_LEFT, foreground, background);
+		self.put_char(right, bottom, BOX_BOTTOM_RIGHT, foreground, background);
+	}
+
+	/// Sets the foreground and background color used for all characters
+	/// written after this call.
+	///
+	/// The highest bit of the background color's number is also the VGA's
+	/// blink bit, so while blinking is enabled (see `set_blink`), a bright
+	/// background color (`DarkGray` to `White`) shows up as the matching dark
+	/// color with blinking text.
+	pub fn set_color(&mut self, foreground: Color, background: Color) {
+		self.cursor.color = CombinedColor::new(foreground, background);
+	}
+
+	/// Sets the cursor's position. Positions outside the terminal (or on the
+	/// status bar) are clamped to its edges, so the cursor always refers to a
+	/// valid cell.
 	pub fn set_cursor(&mut self, x: usize, y: usize) {
-		self.cursor.x = x;
-		self.cursor.y = y;
+		// We can't panic on an invalid position, since the panic handler
+		// would deadlock trying to lock the writer to print the message
+		self.cursor.x = x.min(self.width - 1);
+		self.cursor.y = y.max(self.first_row()).min(self.height - 1);
+		self.update_hardware_cursor();
+	}
+
+	/// Returns the cursor's position as an `(x, y)` pair.
+	pub fn cursor_position(&self) -> (usize, usize) {
+		(self.cursor.x, self.cursor.y)
+	}
+
+	/// Returns the character and the foreground and background color of the
+	/// cell at the given position on the screen, or `None` if the position is
+	/// outside the terminal.
+	pub fn cell_at(&mut self, x: usize, y: usize)
+			-> Option<(u8, Color, Color)> {
+		if x >= self.width || y >= self.height {
+			return None;
+		}
+
+		let cell = self.read_cell(x, y);
+		let color = cell.color;
+		Some((cell.character, color.foreground(), color.background()))
+	}
+
+	/// Moves the blinking hardware cursor to the writer's cursor position.
+	pub fn update_hardware_cursor(&self) {
+		// The hardware cursor's location is given as an offset into the buffer
+		// in cells, split across two CRT controller registers
+		let offset = self.cursor.y * self.width + self.cursor.x;
+		unsafe {
+			outb(CRTC_INDEX, CRTC_CURSOR_HIGH);
+			outb(CRTC_DATA, (offset >> 8) as u8);
+			outb(CRTC_INDEX, CRTC_CURSOR_LOW);
+			outb(CRTC_DATA, offset as u8);
+		}
+	}
+
+	/// Sets whether the highest bit of each cell's background color makes its
+	/// text blink (when enabled), or selects a bright background color (when
+	/// disabled). This applies to every cell on the screen at once.
+	pub fn set_blink(&mut self, blink: bool) {
+		unsafe {
+			// Reset the attribute controller so our first write selects the
+			// register, and read the register's current value
+			inb(INPUT_STATUS);
+			let index = ATTRIBUTE_MODE_CONTROL | ATTRIBUTE_PALETTE_SOURCE;
+			outb(ATTRIBUTE_INDEX, index);
+			let mode = inb(ATTRIBUTE_READ);
+
+			// Reading the value doesn't count as a write, so the next write
+			// sets the register's new value
+			let mode = if blink {
+				mode | MODE_CONTROL_BLINK
+			} else {
+				mode & !MODE_CONTROL_BLINK
+			};
+			outb(ATTRIBUTE_INDEX, mode);
+		}
+		self.blink = blink;
+	}
+
+	/// Returns true if the highest bit of the background color makes text
+	/// blink, rather than selecting a bright background color.
+	pub fn blink(&self) -> bool {
+		self.blink
+	}
+
+	/// Hides the blinking hardware cursor.
+	pub fn disable_cursor(&self) {
+		unsafe {
+			outb(CRTC_INDEX, CRTC_CURSOR_START);
+			outb(CRTC_DATA, 1 << 5);
+		}
 	}
 
 	/// Sets the character of the cell under the cursor to the given character,
 	/// sets its foreground and background color to the cursor's current color,
 	/// and advances the cursor one cell right.
+	///
+	/// ANSI escape sequences are interpreted rather than printed (see
+	/// `escape_byte`).
 	fn write_byte(&mut self, character: u8) {
-		// If there's a `\n`, or the cursor is on the last cell of the line,
-		// then move the cursor to the next line
-		if character == b'\n' || self.cursor.x >= TERM_WIDTH - 1 {
+		// Writing anything while viewing the scrollback history returns the
+		// terminal to its live contents, so the output is visible
+		self.snap_to_bottom();
+
+		// If we're part way through an escape sequence, then this byte belongs
+		// to the sequence
+		if self.escape.state != EscapeState::Normal {
+			self.escape_byte(character);
+			return;
+		}
+
+		// An ESC byte starts a new escape sequence
+		if character == 0x1b {
+			self.escape.state = EscapeState::Escape;
+			return;
+		}
+
+		// A BEL byte rings the bell rather than printing anything
+		if character == 0x07 {
+			speaker::bell();
+			return;
+		}
+
+		// A backspace erases the cell before the cursor rather than printing
+		// anything
+		if character == 0x08 {
+			self.backspace();
+			return;
+		}
+
+		// A `\r` moves the cursor back to the start of the current line, so
+		// that the line can be overwritten
+		if character == b'\r' {
+			self.cursor.x = 0;
+			self.update_hardware_cursor();
+			return;
+		}
+
+		// If there's a `\n`, then move the cursor to the next line
+		if character == b'\n' {
 			self.newline();
 			return;
 		}
 
+		// If the previous character filled the last cell of the line, then
+		// wrap onto the next line before writing this one. Wrapping here
+		// rather than straight after writing to the last cell means that all
+		// columns are usable, and that a `\n` straight after a full line
+		// doesn't leave an empty line behind it
+		if self.cursor.x >= self.width {
+			self.newline();
+		}
+
 		// Set the cursor's current cell
-		// Use a volatile write so that the compiler doesn't optimise out our
-		// write to the buffer
 		let cursor = self.cursor;
-		self.buffer().cells[cursor.y][cursor.x].write(Cell {
+		self.write_cell(cursor.x, cursor.y, Cell {
 			character: character,
 			color: cursor.color,
 		});
 
-		// Move the cursor right by 1. We don't need to check if the cursor is
-		// at the end of a column because we've already done that with the
-		// opening `if` condition in this function
+		// Move the cursor right by 1. This can leave the cursor one past the
+		// end of the line, in which case we wrap when the next character is
+		// written
 		self.cursor.x += 1;
+		self.update_hardware_cursor();
+	}
+
+	/// Handles a byte that's part of an ANSI escape sequence.
+	///
+	/// Only control sequences (`ESC [`) are supported. Of these, we handle SGR
+	/// sequences (`ESC [ ... m`) for setting colors, and `ESC [ y ; x H` for
+	/// moving the cursor. All other sequences are consumed and ignored.
+	fn escape_byte(&mut self, character: u8) {
+		match self.escape.state {
+			EscapeState::Escape => {
+				if character == b'[' {
+					// Start a control sequence with no parameters
+					self.escape.state = EscapeState::Control;
+					self.escape.params = [0; MAX_ESCAPE_PARAMS];
+					self.escape.count = 0;
+				} else {
+					// We don't support any other kind of escape sequence, so
+					// drop it
+					self.escape.state = EscapeState::Normal;
+				}
+			},
+			EscapeState::Control => match character {
+				b'0' ... b'9' => {
+					// Add the digit to the end of the current parameter
+					if self.escape.count == 0 {
+						self.escape.count = 1;
+					}
+					let index = self.escape.count - 1;
+					if index < MAX_ESCAPE_PARAMS {
+						let digit = (character - b'0') as usize;
+						let param = &mut self.escape.params[index];
+						*param = param.saturating_mul(10).saturating_add(digit);
+					}
+				},
+				b';' => {
+					// Move on to the next parameter. A leading `;` means the
+					// first parameter was left empty
+					if self.escape.count == 0 {
+						self.escape.count = 1;
+					}
+					self.escape.count += 1;
+				},
+				0x40 ... 0x7e => {
+					// This is the final byte, which says what the sequence
+					// actually does
+					self.escape.state = EscapeState::Normal;
+					self.run_control_sequence(character);
+				},
+				_ => {
+					// Ignore any intermediate bytes we don't understand
+				},
+			},
+			EscapeState::Normal => {},
+		}
+	}
+
+	/// Returns the parameter at the given index of the current escape
+	/// sequence, or `default` if it wasn't given (or was given as 0).
+	fn escape_param(&self, index: usize, default: usize) -> usize {
+		if index < self.escape.count && index < MAX_ESCAPE_PARAMS &&
+				self.escape.params[index] != 0 {
+			self.escape.params[index]
+		} else {
+			default
+		}
+	}
+
+	/// Performs the action for a completed control sequence, given its final
+	/// byte.
+	fn run_control_sequence(&mut self, command: u8) {
+		match command {
+			b'm' => {
+				// Select graphic rendition (SGR). Each parameter changes part
+				// of the current color, and no parameters means reset
+				let count = self.escape.count.max(1).min(MAX_ESCAPE_PARAMS);
+				for i in 0 .. count {
+					let param = self.escape.params[i];
+					self.select_graphic_rendition(param);
+				}
+			},
+			b'H' | b'f' => {
+				// Move the cursor to a 1-based row and column, keeping it on
+				// the screen
+				let y = self.escape_param(0, 1).min(self.height) - 1;
+				let x = self.escape_param(1, 1).min(self.width) - 1;
+				self.set_cursor(x, y);
+			},
+			_ => {
+				// Ignore any sequences we don't support
+			},
+		}
+	}
+
+	/// Applies a single SGR parameter to the cursor's color.
+	fn select_graphic_rendition(&mut self, param: usize) {
+		let color = self.cursor.color;
+		self.cursor.color = match param {
+			0 => CombinedColor::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+			30 ... 37 => color.with_foreground(ANSI_COLORS[param - 30]),
+			39 => color.with_foreground(DEFAULT_FOREGROUND),
+			40 ... 47 => color.with_background(ANSI_COLORS[param - 40]),
+			49 => color.with_background(DEFAULT_BACKGROUND),
+			90 ... 97 => color.with_foreground(ANSI_BRIGHT_COLORS[param - 90]),
+			_ => color,
+		};
+	}
+
+	/// Writes each byte in a slice to the terminal, exactly as if each were
+	/// passed to `write_byte` in turn.
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		for &byte in bytes {
+			self.write_byte(byte);
+		}
+	}
+
+	/// Moves the cursor back one cell and clears the cell it lands on, leaving
+	/// the cursor on the cleared cell.
+	///
+	/// If the cursor is at the start of a line, it moves to the end of the
+	/// previous line. Nothing happens if the cursor is at the start of the
+	/// first line below the status bar.
+	fn backspace(&mut self) {
+		if self.cursor.x > 0 {
+			self.cursor.x -= 1;
+		} else if self.cursor.y > self.first_row() {
+			self.cursor.y -= 1;
+			self.cursor.x = self.width - 1;
+		} else {
+			// There's nothing before the first cell to erase
+			return;
+		}
+
+		// Replace the cell under the cursor with a space
+		let cursor = self.cursor;
+		self.write_cell(cursor.x, cursor.y, Cell {
+			character: b' ',
+			color: cursor.color,
+		});
+		self.update_hardware_cursor();
+	}
+
+	/// Copies every cell in one row to another.
+	fn copy_row(&mut self, from: usize, to: usize) {
+		for x in 0 .. self.width {
+			let cell = self.read_cell(x, from);
+			self.write_cell(x, to, cell);
+		}
+	}
+
+	/// Deletes a row, moving every row below it up by one and clearing the
+	/// bottom row with the cursor's current colors. The cursor doesn't move.
+	/// Rows outside the terminal or on the status bar are ignored.
+	pub fn delete_line(&mut self, y: usize) {
+		if y < self.first_row() || y >= self.height {
+			return;
+		}
+
+		self.snap_to_bottom();
+		for row in (y + 1) .. self.height {
+			self.copy_row(row, row - 1);
+		}
+		let bottom = self.height - 1;
+		self.clear_row(bottom);
+	}
+
+	/// Inserts an empty row, moving it and every row below it down by one and
+	/// clearing it with the cursor's current colors. The bottom row is lost,
+	/// and the cursor doesn't move. Rows outside the terminal or on the status
+	/// bar are ignored.
+	pub fn insert_line(&mut self, y: usize) {
+		if y < self.first_row() || y >= self.height {
+			return;
+		}
+
+		self.snap_to_bottom();
+		for row in ((y + 1) .. self.height).rev() {
+			self.copy_row(row - 1, row);
+		}
+		self.clear_row(y);
 	}
 
 	/// Scroll the contents of the screen up by a certain amount.
@@ -165,22 +1015,29 @@ impl Writer {
 	/// using a space as the character for each empty cell.
 	///
 	/// The terminal's cursor is moved up with the rest of the screen, leaving
-	/// it in the same location relative to the text around it.
+	/// it in the same location relative to the text around it. The status bar
+	/// doesn't move.
 	fn scroll_up(&mut self, amount: usize) {
-		// Iterate over every row that will still exist when the terminal
-		// screen has been scrolled
-		for y in amount .. TERM_HEIGHT {
-			// Iterate over every character in the row
-			for x in 0 .. TERM_WIDTH {
-				// Replace the character `amount` rows up with this character
-				let buffer = self.buffer();
-				let character = buffer.cells[y][x].read();
-				buffer.cells[y - amount][x].write(character);
+		let first = self.first_row();
+
+		// Save each row that's about to scroll off the top of the screen into
+		// the scrollback history
+		for y in first .. (first + amount) {
+			let mut line = [BLANK_CELL; MAX_WIDTH];
+			for x in 0 .. self.width {
+				line[x] = self.read_cell(x, y);
 			}
+			self.scrollback.push(line);
+		}
+
+		// Move every row that will still exist when the terminal screen has
+		// been scrolled up by `amount` rows
+		for y in (first + amount) .. self.height {
+			self.copy_row(y, y - amount);
 		}
 
 		// Clear each empty row at the bottom of the screen
-		for y in (TERM_HEIGHT - amount) .. TERM_HEIGHT {
+		for y in (self.height - amount) .. self.height {
 			self.clear_row(y);
 		}
 
@@ -189,27 +1046,105 @@ impl Writer {
 		self.cursor.y -= amount;
 	}
 
+	/// Shows lines further back in the scrollback history, moving the view up
+	/// by the given number of lines. The cursor isn't moved.
+	pub fn page_up(&mut self, lines: usize) {
+		if self.scrollback.count == 0 {
+			return;
+		}
+
+		// Save the terminal's live contents if we're only starting to view the
+		// history now, so we can restore them later
+		if self.scrollback.offset == 0 {
+			for y in 0 .. self.height {
+				for x in 0 .. self.width {
+					let cell = self.read_cell(x, y);
+					self.scrollback.screen[y][x] = cell;
+				}
+			}
+		}
+
+		// We can't move up past the oldest line in the history
+		let offset = self.scrollback.offset + lines;
+		self.scrollback.offset = offset.min(self.scrollback.count);
+		self.repaint_scrollback();
+	}
+
+	/// Moves the view of the scrollback history down by the given number of
+	/// lines, returning to the terminal's live contents when we reach the
+	/// bottom.
+	pub fn page_down(&mut self, lines: usize) {
+		if self.scrollback.offset == 0 {
+			return;
+		}
+
+		if lines >= self.scrollback.offset {
+			self.snap_to_bottom();
+		} else {
+			self.scrollback.offset -= lines;
+			self.repaint_scrollback();
+		}
+	}
+
+	/// Restores the terminal's live contents if we're viewing the scrollback
+	/// history.
+	fn snap_to_bottom(&mut self) {
+		if self.scrollback.offset == 0 {
+			return;
+		}
+
+		self.scrollback.offset = 0;
+		for y in 0 .. self.height {
+			for x in 0 .. self.width {
+				let cell = self.scrollback.screen[y][x];
+				self.write_cell(x, y, cell);
+			}
+		}
+	}
+
+	/// Redraws the terminal (except the status bar) to show the part of the
+	/// scrollback history given by the current offset.
+	fn repaint_scrollback(&mut self) {
+		// Treat the history and the saved live contents (below the status bar)
+		// as one long list of lines, and find the first line that's visible at
+		// the current offset
+		let first = self.first_row();
+		let count = self.scrollback.count;
+		let top = count - self.scrollback.offset;
+		for y in first .. self.height {
+			let index = top + y - first;
+			let line = if index < count {
+				self.scrollback.line(index)
+			} else {
+				self.scrollback.screen[index - count + first]
+			};
+
+			for x in 0 .. self.width {
+				self.write_cell(x, y, line[x]);
+			}
+		}
+	}
+
 	/// Advances the cursor to the next line, and moves it to the start of this
 	/// next line. If the cursor is at the bottom of the screen, then shifts
 	/// all existing lines up by 1.
 	fn newline(&mut self) {
 		// Check if the cursor is on the last line of the terminal, in which
 		// case we need to scroll the contents of the terminal up by 1
-		if self.cursor.y >= TERM_HEIGHT - 1 {
+		if self.cursor.y >= self.height - 1 {
 			self.scroll_up(1);
 		}
 
 		// Move the cursor to the start of the next line
 		self.cursor.y += 1;
 		self.cursor.x = 0;
+		self.update_hardware_cursor();
 	}
 }
 
 impl fmt::Write for Writer {
 	fn write_str(&mut self, string: &str) -> fmt::Result {
-		for byte in string.bytes() {
-			self.write_byte(byte);
-		}
+		self.write_bytes(string.as_bytes());
 
 		// Writing using VGA can't really generate any errors, so always return
 		// OK here
@@ -217,39 +1152,47 @@ impl fmt::Write for Writer {
 	}
 }
 
+impl Console for Writer {
+	fn set_color(&mut self, foreground: Color, background: Color) {
+		Writer::set_color(self, foreground, background);
+	}
 
-/// Initialise the VGA module.
-///
-/// Clears the screen and moves the cursor to the origin.
-pub fn init() {
-	// Clear the screen and set the cursor position to the origin, since the
-	// bootloader would've printed a bunch of messages before us
-	let mut writer = WRITER.lock();
-	writer.clear_screen();
-	writer.set_cursor(0, 0);
-}
+	fn color(&self) -> (Color, Color) {
+		(self.cursor.color.foreground(), self.cursor.color.background())
+	}
 
+	fn clear(&mut self) {
+		self.clear_screen();
+		self.set_cursor(0, 0);
+	}
 
-/// A macro to print a format string and arguments to the terminal.
-macro_rules! print {
-    ($($arg:tt)*) => ({
-        $crate::driver::vga::print(format_args!($($arg)*));
-    });
-}
+	fn set_cursor(&mut self, x: usize, y: usize) {
+		Writer::set_cursor(self, x, y);
+	}
+
+	fn reset(&mut self) {
+		Writer::reset(self);
+	}
 
-/// Prints a string to the terminal, appending a newline after it.
-macro_rules! println {
-    ($fmt:expr) => (print!(concat!($fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+	fn flush(&mut self) {
+		Writer::flush(self);
+	}
 }
 
-/// Prints a series of format arguments to the terminal.
-pub fn print(args: fmt::Arguments) {
-	// This is required (instead of just inlining this in the `print!` macro) to
-	// avoid a deadlock of the spin mutex around the VGA writer. Eg. in the code
-	// `println!("something {}", { println!("else"); 3 })`, we'd call the
-	// writer's `lock()` function twice, causing a deadlock. By moving the call
-	// to the mutex's lock function into a separate function, we avoid this.
-    use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+
+/// Stops the module from being initialised more than once.
+static INIT: Once<()> = Once::new();
+
+/// Initialise the VGA module.
+///
+/// Clears the screen and moves the cursor to the origin.
+///
+/// Does nothing if the module has already been initialised.
+pub fn init() {
+	INIT.call_once(|| {
+		// Clear the screen and set the cursor position to the origin, since the
+		// bootloader would've printed a bunch of messages before us
+		WRITER.lock().reset();
+	});
 }
+
@@ -3,7 +3,7 @@
 //  Kernel Main Entry Point
 //
 
-#![feature(lang_items, unique, const_fn)]
+#![feature(lang_items, unique, const_fn, asm, abi_x86_interrupt)]
 #![no_std]
 
 // A very basic crate that wraps a type so that the only way to access its
@@ -27,6 +27,14 @@ extern crate spin;
 extern crate rlibc;
 
 #[macro_use] mod driver;
+mod arch;
+mod debug;
+#[cfg(not(feature = "tests"))] mod shell;
+mod sync;
+#[cfg(feature = "tests")] mod tests;
+
+use core::fmt;
+use driver::vga::Color;
 
 // This is the main Rust entry point for the kernel, called from the `start.asm`
 // code after a bunch of configuration (like switching to long mode) is done.
@@ -36,10 +44,32 @@ extern crate rlibc;
 #[no_mangle]
 pub extern fn kernel_main(multiboot_ptr: usize) {
 	driver::vga::init();
+	driver::serial::init();
+	arch::gdt::init();
+	arch::interrupts::init();
+	driver::pic::init();
+	driver::keyboard::init();
+	driver::pit::init();
+	arch::interrupts::enable_interrupts();
+	arch::tsc::calibrate();
 	println!("HI");
+	serial_println!("Booted at {}", driver::rtc::now());
+
+	// Enable the no-execute bit for page table entries before we create any
+	// mappings that use it, and make sure the kernel can't write to read only
+	// pages
+	arch::paging::enable_nxe();
+	arch::paging::enable_write_protect();
 
-	// Don't return back to assembly
-	loop {}
+	// When built for testing, run the kernel's tests instead of continuing to
+	// boot normally
+	#[cfg(feature = "tests")]
+	tests::run();
+
+	// Otherwise hand the keyboard over to the shell, which never returns back
+	// to assembly
+	#[cfg(not(feature = "tests"))]
+	shell::run();
 }
 
 #[lang = "eh_personality"]
@@ -51,7 +81,27 @@ extern fn eh_personality() {
 // print an error message and not return.
 #[lang = "panic_fmt"]
 #[no_mangle]
-pub extern fn panic_fmt() -> ! {
+pub extern fn panic_fmt(fmt: fmt::Arguments, file: &'static str, line: u32) -> ! {
+	let registers = debug::Registers::capture();
+
+	// Print the panic message in red so it stands out from everything else on
+	// the screen
+	color_println!(Color::Red, Color::Black, "KERNEL PANIC at {}:{}: {}", file, line, fmt);
+	serial_println!("KERNEL PANIC at {}:{}: {}", file, line, fmt);
+	println!("registers at panic:");
+	serial_println!("registers at panic:");
+	registers.dump();
+	debug::backtrace();
+
+	// A panic while testing means a test failed
+	#[cfg(feature = "tests")]
+	tests::fail();
+
+	// Reboot instead of hanging if the kernel was built to
+	#[cfg(all(feature = "reboot_on_panic", not(feature = "tests")))]
+	arch::cpu::reboot();
+
 	// Make sure this function doesn't return (required by the ! return type)
-	loop {}
+	#[cfg(not(any(feature = "reboot_on_panic", feature = "tests")))]
+	arch::cpu::halt_loop();
 }
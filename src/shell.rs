@@ -0,0 +1,72 @@
+
+//
+//  Kernel Shell
+//
+
+use core::str;
+
+use driver::console;
+use driver::keyboard;
+
+/// The longest line the shell reads. Anything typed past this is ignored.
+const LINE_SIZE: usize = 128;
+
+/// The most words a line is split into, including the command. Any extra
+/// words are ignored.
+const MAX_ARGS: usize = 16;
+
+/// Every command the shell understands, as a name and a function that's called
+/// with the words after the command's name.
+const COMMANDS: &[(&str, fn(&[&str]))] = &[
+	("help", help),
+	("clear", clear),
+];
+
+/// Runs the shell forever, reading a line from the keyboard and running the
+/// command it names.
+pub fn run() -> ! {
+	let mut line = [0; LINE_SIZE];
+	loop {
+		print!("> ");
+		let length = keyboard::read_line(&mut line);
+
+		// `read_line` only stores printable ASCII characters, so the line is
+		// always valid UTF-8
+		execute(str::from_utf8(&line[.. length]).unwrap_or(""));
+	}
+}
+
+/// Splits a line into words, and runs the command named by the first word
+/// with the rest as its arguments.
+fn execute(line: &str) {
+	let mut words = [""; MAX_ARGS];
+	let mut count = 0;
+	for word in line.split_whitespace().take(MAX_ARGS) {
+		words[count] = word;
+		count += 1;
+	}
+
+	// Ignore empty lines
+	if count == 0 {
+		return;
+	}
+
+	let name = words[0];
+	match COMMANDS.iter().find(|&&(command, _)| command == name) {
+		Some(&(_, function)) => function(&words[1 .. count]),
+		None => println!("unknown command `{}`, type `help` for a list", name),
+	}
+}
+
+/// Lists every command.
+fn help(_args: &[&str]) {
+	println!("commands:");
+	for &(name, _) in COMMANDS {
+		println!("  {}", name);
+	}
+}
+
+/// Clears the console, and resets its colors in case a command changed them.
+fn clear(_args: &[&str]) {
+	console::with_console(|console| console.reset());
+}
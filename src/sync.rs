@@ -0,0 +1,60 @@
+
+//
+//  Synchronisation Primitives
+//
+
+use core::ops::{Deref, DerefMut};
+
+use spin::{Mutex, MutexGuard};
+use arch::interrupts::InterruptGuard;
+
+/// A spin lock that disables interrupts while it's held.
+///
+/// A plain spin lock taken by both normal code and an interrupt handler
+/// deadlocks if the interrupt arrives while the lock is held, since the handler
+/// spins forever waiting for code that can't run until it returns. Disabling
+/// interrupts for as long as the lock is held stops this from happening.
+pub struct IrqMutex<T> {
+	inner: Mutex<T>,
+}
+
+impl<T> IrqMutex<T> {
+	/// Creates a new lock around a value.
+	pub const fn new(value: T) -> IrqMutex<T> {
+		IrqMutex {
+			inner: Mutex::new(value),
+		}
+	}
+
+	/// Disables interrupts, then takes the lock. Interrupts are restored to
+	/// their previous state once the returned guard is dropped.
+	pub fn lock(&self) -> IrqMutexGuard<T> {
+		let interrupts = InterruptGuard::new();
+		IrqMutexGuard {
+			guard: self.inner.lock(),
+			_interrupts: interrupts,
+		}
+	}
+}
+
+/// Gives access to the value inside an `IrqMutex` while it's locked.
+pub struct IrqMutexGuard<'a, T: 'a> {
+	// Fields are dropped in order, so the lock is released before interrupts
+	// are restored
+	guard: MutexGuard<'a, T>,
+	_interrupts: InterruptGuard,
+}
+
+impl<'a, T> Deref for IrqMutexGuard<'a, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&*self.guard
+	}
+}
+
+impl<'a, T> DerefMut for IrqMutexGuard<'a, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut *self.guard
+	}
+}
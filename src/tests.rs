@@ -0,0 +1,174 @@
+//
+//  Kernel Test Harness
+//
+
+use arch::qemu::{qemu_exit, QemuExitCode};
+use driver::speaker;
+use driver::vga::{Color, WRITER};
+
+/// Every test the harness runs, as a name and a function that panics if the
+/// test fails.
+const TESTS: &[(&str, fn())] = &[
+	("vga_write_text", vga_write_text),
+	("vga_wrap_full_line", vga_wrap_full_line),
+	("vga_carriage_return", vga_carriage_return),
+	("vga_ansi_color_and_cursor", vga_ansi_color_and_cursor),
+	("vga_delete_line", vga_delete_line),
+	("vga_insert_line", vga_insert_line),
+	("vga_status_bar_keeps_wrap", vga_status_bar_keeps_wrap),
+];
+
+/// Runs every test, printing the result of each to the serial port, then exits
+/// QEMU.
+///
+/// A failing test panics, and the panic handler calls `fail` to exit QEMU with
+/// a failure code. So we only reach the end of this function if every test
+/// passed.
+pub fn run() -> ! {
+	// Don't beep at whoever's running the tests
+	speaker::set_quiet(true);
+
+	serial_println!("running {} tests", TESTS.len());
+	for &(name, test) in TESTS {
+		serial_print!("{} ... ", name);
+		test();
+		serial_println!("[ok]");
+	}
+
+	qemu_exit(QemuExitCode::Success);
+}
+
+/// Reports that the current test failed and exits QEMU. Called by the panic
+/// handler.
+pub fn fail() -> ! {
+	serial_println!("[failed]");
+	qemu_exit(QemuExitCode::Failed);
+}
+
+
+// The VGA tests below only hold the writer's lock for as long as each call
+// takes. A failed assertion panics, and the panic handler prints through the
+// writer, so it'd deadlock if the lock was still held.
+
+/// Puts the VGA writer back to its defaults and clears the screen, so each
+/// test starts from the same state.
+fn reset_screen() {
+	WRITER.lock().reset();
+}
+
+/// Writes text to the VGA writer, exactly as `print!` would.
+fn write(text: &str) {
+	WRITER.lock().write_bytes(text.as_bytes());
+}
+
+/// Checks that a row on the screen holds the expected text, followed by
+/// nothing but spaces.
+fn assert_row(y: usize, expected: &str) {
+	let (width, _) = WRITER.lock().dimensions();
+	for x in 0 .. width {
+		let wanted = expected.as_bytes().get(x).cloned().unwrap_or(b' ');
+		let found = WRITER.lock().cell_at(x, y)
+			.map(|(character, _, _)| character);
+		assert!(found == Some(wanted), "expected {:?} at ({}, {}), found {:?}",
+			wanted as char, x, y, found.map(|character| character as char));
+	}
+}
+
+/// Text is written to the screen starting at the cursor.
+fn vga_write_text() {
+	reset_screen();
+	write("hello");
+	assert_row(0, "hello");
+	assert_row(1, "");
+	let cursor = WRITER.lock().cursor_position();
+	assert_eq!(cursor, (5, 0));
+}
+
+/// A full line of 80 characters fills every column, and the next character
+/// wraps onto the following row.
+fn vga_wrap_full_line() {
+	reset_screen();
+	for _ in 0 .. 80 {
+		write("a");
+	}
+	let cursor = WRITER.lock().cursor_position();
+	assert_eq!(cursor, (80, 0));
+
+	write("b");
+	assert_row(0, "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\
+		aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+	assert_row(1, "b");
+}
+
+/// A carriage return moves the cursor back to the start of the line, so the
+/// next character overwrites the first.
+fn vga_carriage_return() {
+	reset_screen();
+	write("abc\rX");
+	assert_row(0, "Xbc");
+	let cursor = WRITER.lock().cursor_position();
+	assert_eq!(cursor, (1, 0));
+}
+
+/// An SGR sequence changes the color of the text after it, and a cursor
+/// position sequence moves the cursor (counting from 1). Neither is printed.
+fn vga_ansi_color_and_cursor() {
+	reset_screen();
+	write("\x1b[31mR\x1b[0mN");
+	assert_row(0, "RN");
+	let red = WRITER.lock().cell_at(0, 0);
+	assert_eq!(red, Some((b'R', Color::Red, Color::Black)));
+	let normal = WRITER.lock().cell_at(1, 0);
+	assert_eq!(normal, Some((b'N', Color::White, Color::Black)));
+
+	write("\x1b[5;10HZ");
+	assert_row(4, "         Z");
+	let cursor = WRITER.lock().cursor_position();
+	assert_eq!(cursor, (10, 4));
+}
+
+/// Deleting a line moves every row below it up by one, and clears the bottom
+/// row, without moving the cursor.
+fn vga_delete_line() {
+	reset_screen();
+	write("r0\nr1\nr2\nr3");
+	WRITER.lock().delete_line(1);
+	assert_row(0, "r0");
+	assert_row(1, "r2");
+	assert_row(2, "r3");
+	assert_row(3, "");
+	assert_row(24, "");
+	let cursor = WRITER.lock().cursor_position();
+	assert_eq!(cursor, (2, 3));
+}
+
+/// Inserting a line moves it and every row below it down by one, leaving an
+/// empty row in its place.
+fn vga_insert_line() {
+	reset_screen();
+	write("r0\nr1\nr2");
+	WRITER.lock().insert_line(1);
+	assert_row(0, "r0");
+	assert_row(1, "");
+	assert_row(2, "r1");
+	assert_row(3, "r2");
+	let cursor = WRITER.lock().cursor_position();
+	assert_eq!(cursor, (2, 2));
+}
+
+/// Enabling the status bar after a full line doesn't move the cursor back
+/// into the line, so the next character still wraps.
+fn vga_status_bar_keeps_wrap() {
+	reset_screen();
+	write("\n");
+	for _ in 0 .. 80 {
+		write("a");
+	}
+	WRITER.lock().set_status_bar(true);
+	write("b");
+	WRITER.lock().set_status_bar(false);
+
+	assert_row(1, "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\
+		aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+	assert_row(2, "b");
+}